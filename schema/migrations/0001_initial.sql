@@ -39,4 +39,4 @@ CREATE TABLE IF NOT EXISTS covid_province(
     meninggal BIGINT,
     prov_and_date TEXT NOT NULL UNIQUE,
     existed BOOL DEFAULT false
-);
\ No newline at end of file
+);
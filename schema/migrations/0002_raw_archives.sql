@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS raw_archives(
+    id BIGSERIAL PRIMARY KEY,
+    dataset TEXT NOT NULL,
+    url TEXT NOT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    path TEXT NOT NULL,
+    compressed BOOL NOT NULL DEFAULT false,
+    bytes BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS raw_archives_sha256_idx ON raw_archives(sha256);
+CREATE INDEX IF NOT EXISTS raw_archives_dataset_fetched_at_idx ON raw_archives(dataset, fetched_at);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS observed_schemas(
+    id BIGSERIAL PRIMARY KEY,
+    dataset TEXT NOT NULL,
+    observed_at TIMESTAMPTZ NOT NULL,
+    fields TEXT NOT NULL,
+    fingerprint CHAR(64) NOT NULL,
+    drift TEXT
+);
+
+CREATE INDEX IF NOT EXISTS observed_schemas_dataset_observed_at_idx ON observed_schemas(dataset, observed_at);
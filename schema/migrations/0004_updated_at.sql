@@ -0,0 +1,3 @@
+ALTER TABLE covid_stats ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
+ALTER TABLE covid_daily ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
+ALTER TABLE covid_province ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
@@ -0,0 +1,36 @@
+CREATE TABLE IF NOT EXISTS revision_history(
+    id BIGSERIAL PRIMARY KEY,
+    table_name TEXT NOT NULL,
+    row_id BIGINT NOT NULL,
+    old_values JSONB NOT NULL,
+    new_values JSONB NOT NULL,
+    run_id TEXT,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS revision_history_table_row_idx ON revision_history(table_name, row_id);
+
+-- bookkeeping columns are left out so only revised figures are recorded
+CREATE OR REPLACE FUNCTION record_revision() RETURNS trigger AS $$
+DECLARE
+    old_row JSONB := to_jsonb(OLD) - 'id' - 'existed' - 'created_at' - 'updated_at';
+    new_row JSONB := to_jsonb(NEW) - 'id' - 'existed' - 'created_at' - 'updated_at';
+BEGIN
+    IF old_row IS DISTINCT FROM new_row THEN
+        INSERT INTO revision_history(table_name, row_id, old_values, new_values, run_id)
+        VALUES (TG_TABLE_NAME, OLD.id, old_row, new_row,
+            NULLIF(current_setting('inacovid.run_id', true), ''));
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS covid_stats_revision ON covid_stats;
+CREATE TRIGGER covid_stats_revision AFTER UPDATE ON covid_stats
+    FOR EACH ROW EXECUTE PROCEDURE record_revision();
+DROP TRIGGER IF EXISTS covid_daily_revision ON covid_daily;
+CREATE TRIGGER covid_daily_revision AFTER UPDATE ON covid_daily
+    FOR EACH ROW EXECUTE PROCEDURE record_revision();
+DROP TRIGGER IF EXISTS covid_province_revision ON covid_province;
+CREATE TRIGGER covid_province_revision AFTER UPDATE ON covid_province
+    FOR EACH ROW EXECUTE PROCEDURE record_revision();
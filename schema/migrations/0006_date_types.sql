@@ -0,0 +1,20 @@
+-- Dates used to be stored as text produced from naive UTC timestamps.
+-- Reporting days are Asia/Jakarta calendar days: the upstream `Tanggal` of a day
+-- is its midnight in WIB, i.e. 17:00 UTC of the previous day.
+ALTER TABLE covid_daily
+    ALTER COLUMN date TYPE DATE
+        USING ((date::timestamp AT TIME ZONE 'UTC') AT TIME ZONE 'Asia/Jakarta')::date,
+    ALTER COLUMN latest_update TYPE TIMESTAMPTZ
+        USING latest_update::timestamp AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ,
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ;
+
+ALTER TABLE covid_province
+    ALTER COLUMN date TYPE DATE
+        USING ((date::timestamp AT TIME ZONE 'UTC') AT TIME ZONE 'Asia/Jakarta')::date,
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ;
+
+ALTER TABLE covid_stats
+    ALTER COLUMN at_date TYPE DATE USING trim(at_date)::date,
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ,
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ;
@@ -0,0 +1,15 @@
+-- Reference data of the provinces keyed by their BPS code, seeded from
+-- data/provinces.json whenever migrations are run. Population is from the
+-- 2020 census (SP2020).
+CREATE TABLE IF NOT EXISTS province(
+    code BIGINT PRIMARY KEY,
+    name_id TEXT NOT NULL,
+    name_en TEXT NOT NULL,
+    iso_3166_2 TEXT NOT NULL UNIQUE,
+    island_group TEXT NOT NULL,
+    population BIGINT,
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+-- rows whose code or name could not be resolved against the province table
+ALTER TABLE covid_province ADD COLUMN IF NOT EXISTS unknown_province BOOL NOT NULL DEFAULT false;
@@ -0,0 +1,13 @@
+-- prov_and_date was built from the date truncated to `YYYY-MM-D`, so up to ten
+-- days of a province collapsed into one row. Snapshots are now keyed on the
+-- province code and reporting day. Should the old key have let two rows claim
+-- the same pair (e.g. a province spelled differently upstream), the most
+-- recently written one is kept.
+DELETE FROM covid_province c
+USING covid_province d
+WHERE c.province_id = d.province_id AND c.date = d.date
+    AND (COALESCE(c.updated_at, '-infinity'), c.id) < (COALESCE(d.updated_at, '-infinity'), d.id);
+
+ALTER TABLE covid_province DROP COLUMN IF EXISTS prov_and_date;
+ALTER TABLE covid_province
+    ADD CONSTRAINT covid_province_province_id_date_key UNIQUE (province_id, date);
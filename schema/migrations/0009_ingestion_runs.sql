@@ -0,0 +1,13 @@
+-- one row per invocation of the ingestion, `counts` holds the upsert counts
+-- of every dataset that was committed
+CREATE TABLE IF NOT EXISTS ingestion_runs(
+    run_id TEXT PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+    status TEXT NOT NULL,
+    atomic BOOL NOT NULL DEFAULT false,
+    counts JSONB,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS ingestion_runs_started_at_idx ON ingestion_runs(started_at);
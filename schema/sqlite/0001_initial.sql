@@ -0,0 +1,116 @@
+-- The Postgres schema as of its migration 9. Dates are stored as `YYYY-MM-DD`
+-- text, timestamps as RFC 3339 text in UTC and booleans as 0/1. Revisions are
+-- recorded by the store rather than by triggers.
+CREATE TABLE IF NOT EXISTS covid_stats(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    deaths INTEGER,
+    total_cases INTEGER,
+    recovered INTEGER,
+    pdp INTEGER,
+    at_date TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    updated_at TEXT,
+    existed INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS covid_daily(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    day INTEGER,
+    date TEXT NOT NULL UNIQUE,
+    new_cases_per_day INTEGER,
+    cumulative_cases INTEGER,
+    under_treatment INTEGER,
+    under_treatment_per_day INTEGER,
+    under_treatment_percentage REAL,
+    recovered INTEGER,
+    recovered_per_day INTEGER,
+    recovered_percentage REAL,
+    deaths INTEGER,
+    deaths_per_day INTEGER,
+    deaths_percentage REAL,
+    latest_update TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    updated_at TEXT,
+    existed INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS covid_province(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    province_id INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    provinsi TEXT,
+    positif INTEGER,
+    sembuh INTEGER,
+    meninggal INTEGER,
+    unknown_province INTEGER NOT NULL DEFAULT 0,
+    updated_at TEXT,
+    existed INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (province_id, date)
+);
+
+CREATE TABLE IF NOT EXISTS province(
+    code INTEGER PRIMARY KEY,
+    name_id TEXT NOT NULL,
+    name_en TEXT NOT NULL,
+    iso_3166_2 TEXT NOT NULL UNIQUE,
+    island_group TEXT NOT NULL,
+    population INTEGER,
+    updated_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS raw_archives(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    dataset TEXT NOT NULL,
+    url TEXT NOT NULL,
+    fetched_at TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    path TEXT NOT NULL,
+    compressed INTEGER NOT NULL DEFAULT 0,
+    bytes INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS raw_archives_sha256_idx ON raw_archives(sha256);
+CREATE INDEX IF NOT EXISTS raw_archives_dataset_fetched_at_idx ON raw_archives(dataset, fetched_at);
+
+CREATE TABLE IF NOT EXISTS observed_schemas(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    dataset TEXT NOT NULL,
+    observed_at TEXT NOT NULL,
+    fields TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    drift TEXT
+);
+
+CREATE INDEX IF NOT EXISTS observed_schemas_dataset_observed_at_idx ON observed_schemas(dataset, observed_at);
+
+CREATE TABLE IF NOT EXISTS revision_history(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    table_name TEXT NOT NULL,
+    row_id INTEGER NOT NULL,
+    old_values TEXT NOT NULL,
+    new_values TEXT NOT NULL,
+    run_id TEXT,
+    changed_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS revision_history_table_row_idx ON revision_history(table_name, row_id);
+
+CREATE TABLE IF NOT EXISTS ingestion_runs(
+    run_id TEXT PRIMARY KEY,
+    started_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    finished_at TEXT,
+    status TEXT NOT NULL,
+    atomic INTEGER NOT NULL DEFAULT 0,
+    counts TEXT,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS ingestion_runs_started_at_idx ON ingestion_runs(started_at);
+
+-- stands in for the advisory lock of Postgres, at most one row
+CREATE TABLE IF NOT EXISTS run_lock(
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    run_id TEXT NOT NULL,
+    pid INTEGER NOT NULL,
+    acquired_at TEXT NOT NULL
+)
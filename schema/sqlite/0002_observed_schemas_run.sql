@@ -0,0 +1,2 @@
+-- the ingestion run that observed the fields, NULL for rows recorded before
+ALTER TABLE observed_schemas ADD COLUMN run_id TEXT;
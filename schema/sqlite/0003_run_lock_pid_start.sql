@@ -0,0 +1,2 @@
+-- the start time of the locking process, tells a reused pid from the holder
+ALTER TABLE run_lock ADD COLUMN pid_start INTEGER;
@@ -0,0 +1,101 @@
+use crate::store::Store;
+use chrono::{DateTime, Utc};
+use failure::Error;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+const RAW_DIR: &str = "raw";
+const MANIFEST_FILE: &str = "manifest.jsonl";
+
+// ------ Raw response archive config -------- //
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    pub compress: bool,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            enabled: true,
+            compress: false,
+        }
+    }
+}
+
+// one line of the manifest, also mirrored to the raw_archives table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub dataset: String,
+    pub url: String,
+    #[serde(rename = "fetchedAt")]
+    pub fetched_at: DateTime<Utc>,
+    pub sha256: String,
+    pub path: String,
+    pub compressed: bool,
+    pub bytes: i64,
+}
+
+impl ArchiveEntry {
+    pub async fn insert_db(&self, store: &dyn Store) -> Result<(), Error> {
+        store.insert_archive(self).await
+    }
+}
+
+pub struct Archiver {
+    dir: String,
+    compress: bool,
+}
+
+impl Archiver {
+    pub fn new(jsondir: &str, config: &ArchiveConfig) -> Result<Self, Error> {
+        let dir = format!("{}{}/", jsondir, RAW_DIR);
+        create_dir_all(dir.as_str())?;
+        Ok(Archiver {
+            dir: dir,
+            compress: config.compress,
+        })
+    }
+
+    // writes the response body as-is and appends its entry to the manifest
+    pub fn store(&self, dataset: &str, url: &str, body: &str) -> Result<ArchiveEntry, Error> {
+        let fetched_at = Utc::now();
+        let sha256 = format!("{:x}", Sha256::digest(body.as_bytes()));
+        let path = format!(
+            "{}{}-{}-{}.json{}",
+            self.dir,
+            dataset,
+            fetched_at.format("%Y%m%dT%H%M%S%.3fZ"),
+            &sha256[..12],
+            if self.compress { ".gz" } else { "" }
+        );
+        let mut file = File::create(path.as_str())?;
+        if self.compress {
+            let mut gz = GzEncoder::new(file, Compression::default());
+            gz.write_all(body.as_bytes())?;
+            gz.finish()?;
+        } else {
+            file.write_all(body.as_bytes())?;
+        }
+        let entry = ArchiveEntry {
+            dataset: dataset.to_string(),
+            url: url.to_string(),
+            fetched_at: fetched_at,
+            sha256: sha256,
+            path: path,
+            compressed: self.compress,
+            bytes: body.len() as i64,
+        };
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Path::new(self.dir.as_str()).join(MANIFEST_FILE))?;
+        writeln!(manifest, "{}", serde_json::to_string(&entry)?)?;
+        Ok(entry)
+    }
+}
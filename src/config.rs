@@ -0,0 +1,84 @@
+use crate::archive::ArchiveConfig;
+use crate::drift::DriftPolicy;
+use crate::export::ParquetConfig;
+use crate::helpers::{default_publish_cutoff, EndpointsConfig};
+use crate::output::{OutputConfig, OutputFormats};
+use crate::queries::HttpConfig;
+use crate::retry::RetryPolicy;
+use crate::run::LockPolicy;
+use chrono::NaiveTime;
+use failure::Error;
+use std::fs::create_dir_all;
+
+pub const DEFAULT_JSON_DIR: &str = "/tmp/inacovid/json_out/";
+
+fn default_bulk_threshold() -> usize {
+    500
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(rename = "postgresDsn")]
+    pub postgres_dsn: Option<String>,
+    #[serde(rename = "jsonOutputDir")]
+    pub json_dir: Option<String>,
+    #[serde(default, rename = "jsonOutput")]
+    pub json_output: OutputConfig,
+    #[serde(default, rename = "outputFormats")]
+    pub output_formats: OutputFormats,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub endpoints: EndpointsConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default, rename = "schemaDrift")]
+    pub schema_drift: DriftPolicy,
+    // WIB time after which today's figures are expected upstream
+    #[serde(default = "default_publish_cutoff", rename = "publishCutoff")]
+    pub publish_cutoff: NaiveTime,
+    // commit all datasets of a run together or none at all
+    #[serde(default, rename = "atomicRun")]
+    pub atomic_run: bool,
+    // when another process is mid-run: "wait", "skip" or "fail"
+    #[serde(default, rename = "runLock")]
+    pub run_lock: LockPolicy,
+    // datasets with at least this many rows are staged and merged in bulk, 0 disables it
+    #[serde(default = "default_bulk_threshold", rename = "bulkThreshold")]
+    pub bulk_threshold: usize,
+    #[serde(default)]
+    pub parquet: ParquetConfig,
+}
+
+impl Config {
+    pub fn json_dir(&self) -> String {
+        self.json_dir
+            .clone()
+            .unwrap_or_else(|| DEFAULT_JSON_DIR.to_string())
+    }
+    pub fn parquet_dir(&self) -> String {
+        self.parquet
+            .dir
+            .clone()
+            .unwrap_or_else(|| format!("{}parquet/", self.json_dir()))
+    }
+    pub fn dashboard_path(&self) -> String {
+        format!("{}dashboard/index.html", self.json_dir())
+    }
+    pub fn create_json_dir(&self) -> Result<(), std::io::Error> {
+        create_dir_all(self.json_dir())
+    }
+    pub fn set_db_dsn(&self) -> Result<(), Error> {
+        match &self.postgres_dsn {
+            Some(dsn) => {
+                std::env::set_var("DATABASE_URI", std::ffi::OsStr::new(dsn));
+                Ok(())
+            }
+            None => Err(format_err!(
+                "postgres dsn is not set or its value is not valid"
+            )),
+        }
+    }
+}
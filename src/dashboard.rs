@@ -0,0 +1,341 @@
+use crate::helpers::jakarta;
+use crate::output::write_atomic;
+use crate::store::Store;
+use chrono::DateTime;
+use failure::Error;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// ------ Static HTML dashboard -------- //
+// A single self-contained page: styles, SVG charts and the table sorting
+// script are all inline, so it can be served from anywhere without a CDN.
+
+const CHART_WIDTH: f64 = 760.0;
+const CHART_HEIGHT: f64 = 240.0;
+const CHART_PAD: f64 = 44.0;
+
+const STYLE: &str = r#"
+body { font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 820px; color: #222; }
+h1 { margin-bottom: 0; }
+.updated { color: #666; margin-top: .25rem; }
+.headline { display: flex; gap: 1rem; flex-wrap: wrap; margin: 1.5rem 0; }
+.headline div { flex: 1; min-width: 150px; padding: .75rem 1rem; border-radius: 6px; background: #f3f4f6; }
+.headline b { display: block; font-size: 1.6rem; }
+.legend span { margin-right: 1rem; }
+.legend i { display: inline-block; width: .8rem; height: .8rem; margin-right: .3rem; }
+svg text { font-size: 11px; fill: #555; }
+table { border-collapse: collapse; width: 100%; }
+th, td { padding: .3rem .5rem; border-bottom: 1px solid #e5e7eb; text-align: right; }
+th:first-child, td:first-child { text-align: left; }
+th { cursor: pointer; user-select: none; }
+"#;
+
+// sorts on the `data-value` of the clicked column, numeric when both sides are
+const SORT_SCRIPT: &str = r#"
+document.querySelectorAll("table.sortable th").forEach(function (th, col) {
+  th.addEventListener("click", function () {
+    var body = th.closest("table").tBodies[0];
+    var asc = th.dataset.order !== "asc";
+    th.dataset.order = asc ? "asc" : "desc";
+    Array.from(body.rows).sort(function (a, b) {
+      var x = a.cells[col].dataset.value, y = b.cells[col].dataset.value;
+      var d = (x !== "" && y !== "" && !isNaN(x) && !isNaN(y)) ? x - y : x.localeCompare(y);
+      return asc ? d : -d;
+    }).forEach(function (row) { body.appendChild(row); });
+  });
+});
+"#;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// 1234567 -> 1,234,567
+fn thousands(n: i64) -> String {
+    let digits = n.abs().to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    if n < 0 {
+        format!("-{}", out)
+    } else {
+        out
+    }
+}
+
+fn count(v: Option<i64>) -> String {
+    v.map(thousands).unwrap_or_else(|| "-".to_string())
+}
+
+fn int(row: &Value, column: &str) -> Option<i64> {
+    row.get(column).and_then(|v| v.as_i64())
+}
+
+fn text<'a>(row: &'a Value, column: &str) -> Option<&'a str> {
+    row.get(column).and_then(|v| v.as_str())
+}
+
+struct Series<'a> {
+    label: &'a str,
+    color: &'a str,
+    values: Vec<Option<f64>>,
+}
+
+// one polyline per series over a shared y axis starting at 0
+fn chart(title: &str, dates: &[&str], series: &[Series]) -> String {
+    let max = series
+        .iter()
+        .flat_map(|s| s.values.iter().flatten())
+        .cloned()
+        .fold(0.0, f64::max);
+    if dates.len() < 2 || max <= 0.0 {
+        return format!("<h2>{}</h2>\n<p>No data yet.</p>\n", escape(title));
+    }
+    let x = |i: usize| {
+        CHART_PAD + (CHART_WIDTH - 2.0 * CHART_PAD) * i as f64 / (dates.len() - 1) as f64
+    };
+    let y = |v: f64| CHART_HEIGHT - CHART_PAD - (CHART_HEIGHT - 2.0 * CHART_PAD) * v / max;
+    let mut svg = format!(
+        "<svg viewBox=\"0 0 {w} {h}\" width=\"100%\" role=\"img\" aria-label=\"{t}\">\n\
+         <line x1=\"{p}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"#999\"/>\n\
+         <line x1=\"{p}\" y1=\"{p}\" x2=\"{p}\" y2=\"{b}\" stroke=\"#999\"/>\n\
+         <text x=\"{l}\" y=\"{p}\" text-anchor=\"end\">{max}</text>\n\
+         <text x=\"{l}\" y=\"{b}\" text-anchor=\"end\">0</text>\n\
+         <text x=\"{p}\" y=\"{d}\">{first}</text>\n\
+         <text x=\"{r}\" y=\"{d}\" text-anchor=\"end\">{last}</text>\n",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        t = escape(title),
+        p = CHART_PAD,
+        b = CHART_HEIGHT - CHART_PAD,
+        r = CHART_WIDTH - CHART_PAD,
+        l = CHART_PAD - 4.0,
+        d = CHART_HEIGHT - CHART_PAD + 16.0,
+        max = thousands(max as i64),
+        first = escape(dates[0]),
+        last = escape(dates[dates.len() - 1]),
+    );
+    let mut legend = vec![];
+    for s in series.iter() {
+        let points = s
+            .values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| format!("{:.1},{:.1}", x(i), y(v))))
+            .collect::<Vec<String>>();
+        svg.push_str(
+            format!(
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n",
+                s.color,
+                points.join(" ")
+            )
+            .as_str(),
+        );
+        legend.push(format!(
+            "<span><i style=\"background:{}\"></i>{}</span>",
+            s.color,
+            escape(s.label)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    format!(
+        "<h2>{}</h2>\n{}<div class=\"legend\">{}</div>\n",
+        escape(title),
+        svg,
+        legend.join("")
+    )
+}
+
+fn daily_series<'a>(rows: &[Value], column: &str, label: &'a str, color: &'a str) -> Series<'a> {
+    Series {
+        label,
+        color,
+        values: rows
+            .iter()
+            .map(|r| r.get(column).and_then(|v| v.as_f64()))
+            .collect(),
+    }
+}
+
+fn headline(stats: Option<&Value>) -> String {
+    let figures = [
+        ("Confirmed", "total_cases"),
+        ("Under treatment", "pdp"),
+        ("Recovered", "recovered"),
+        ("Deaths", "deaths"),
+    ];
+    let cells = figures
+        .iter()
+        .map(|(label, column)| {
+            format!(
+                "<div>{}<b>{}</b></div>",
+                label,
+                count(stats.and_then(|s| int(s, column)))
+            )
+        })
+        .collect::<Vec<String>>();
+    let date = stats.and_then(|s| text(s, "at_date")).unwrap_or("-");
+    format!(
+        "<h2>National figures, {}</h2>\n<div class=\"headline\">{}</div>\n",
+        escape(date),
+        cells.join("")
+    )
+}
+
+// the latest snapshot of every province, with cases per 100k residents
+fn province_table(rows: &[Value], population: &HashMap<i64, i64>) -> String {
+    let latest = rows.iter().filter_map(|r| text(r, "date")).max();
+    let latest = match latest {
+        Some(d) => d,
+        None => return "<h2>Provinces</h2>\n<p>No data yet.</p>\n".to_string(),
+    };
+    let cell = |v: Option<i64>| match v {
+        Some(n) => format!("<td data-value=\"{}\">{}</td>", n, thousands(n)),
+        None => "<td data-value=\"\">-</td>".to_string(),
+    };
+    let mut body = vec![];
+    for row in rows.iter().filter(|r| text(r, "date") == Some(latest)) {
+        let name = text(row, "provinsi").unwrap_or("-");
+        let per_100k = match (
+            int(row, "positif"),
+            int(row, "province_id").and_then(|id| population.get(&id)),
+        ) {
+            (Some(p), Some(pop)) if *pop > 0 => {
+                let v = p as f64 * 100_000.0 / *pop as f64;
+                format!("<td data-value=\"{:.2}\">{:.1}</td>", v, v)
+            }
+            _ => "<td data-value=\"\">-</td>".to_string(),
+        };
+        body.push(format!(
+            "<tr><td data-value=\"{}\">{}</td>{}{}{}{}</tr>",
+            escape(name),
+            escape(name),
+            cell(int(row, "positif")),
+            cell(int(row, "sembuh")),
+            cell(int(row, "meninggal")),
+            per_100k
+        ));
+    }
+    format!(
+        "<h2>Provinces, {}</h2>\n<table class=\"sortable\">\n<thead><tr><th>Province</th><th>Positive</th>\
+         <th>Recovered</th><th>Deaths</th><th>Positive per 100k</th></tr></thead>\n<tbody>\n{}\n</tbody>\n</table>\n",
+        escape(latest),
+        body.join("\n")
+    )
+}
+
+// renders the dashboard from what is stored and writes it to `path`
+pub async fn render(store: &dyn Store, path: &str) -> Result<String, Error> {
+    let stats = store
+        .table_rows(
+            "covid_stats",
+            &["at_date", "total_cases", "pdp", "recovered", "deaths"],
+            "at_date",
+        )
+        .await?;
+    let daily = store
+        .table_rows(
+            "covid_daily",
+            &[
+                "date",
+                "new_cases_per_day",
+                "recovered_per_day",
+                "deaths_per_day",
+                "cumulative_cases",
+                "under_treatment",
+                "recovered",
+                "deaths",
+                "latest_update",
+            ],
+            "date",
+        )
+        .await?;
+    let provinces = store
+        .table_rows(
+            "covid_province",
+            &[
+                "date",
+                "province_id",
+                "provinsi",
+                "positif",
+                "sembuh",
+                "meninggal",
+            ],
+            "date, province_id",
+        )
+        .await?;
+    let population = store
+        .table_rows("province", &["code", "population"], "code")
+        .await?
+        .iter()
+        .filter_map(|r| Some((int(r, "code")?, int(r, "population")?)))
+        .collect::<HashMap<i64, i64>>();
+
+    let mut updated = None;
+    for row in daily.iter() {
+        if let Some(ts) = text(row, "latest_update") {
+            let ts = DateTime::parse_from_rfc3339(ts)?;
+            if updated.map(|u| ts > u).unwrap_or(true) {
+                updated = Some(ts);
+            }
+        }
+    }
+    let updated = match updated {
+        Some(ts) => ts
+            .with_timezone(&jakarta())
+            .format("%Y-%m-%d %H:%M WIB")
+            .to_string(),
+        None => "never".to_string(),
+    };
+
+    let dates = daily
+        .iter()
+        .map(|r| text(r, "date").unwrap_or(""))
+        .collect::<Vec<&str>>();
+    let html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>COVID-19 in Indonesia</title>\n<style>{}</style>\n</head>\n<body>\n\
+         <h1>COVID-19 in Indonesia</h1>\n<p class=\"updated\">Last updated {}</p>\n{}{}{}{}\
+         <script>{}</script>\n</body>\n</html>\n",
+        STYLE,
+        escape(updated.as_str()),
+        headline(stats.last()),
+        chart(
+            "Daily cases",
+            &dates,
+            &[
+                daily_series(&daily, "new_cases_per_day", "New cases", "#d97706"),
+                daily_series(&daily, "recovered_per_day", "Recovered", "#059669"),
+                daily_series(&daily, "deaths_per_day", "Deaths", "#dc2626"),
+            ],
+        ),
+        chart(
+            "Cumulative cases",
+            &dates,
+            &[
+                daily_series(&daily, "cumulative_cases", "Confirmed", "#2563eb"),
+                daily_series(&daily, "under_treatment", "Under treatment", "#d97706"),
+                daily_series(&daily, "recovered", "Recovered", "#059669"),
+                daily_series(&daily, "deaths", "Deaths", "#dc2626"),
+            ],
+        ),
+        province_table(&provinces, &population),
+        SORT_SCRIPT
+    );
+    write_atomic(path, html.as_bytes())?;
+    Ok(format!(
+        "Dashboard written to {} ({} days, last updated {})",
+        path,
+        daily.len(),
+        updated
+    ))
+}
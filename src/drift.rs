@@ -0,0 +1,227 @@
+use crate::helpers::Endpoint;
+use crate::models::Field;
+use crate::store::Store;
+use chrono::{DateTime, Utc};
+use failure::Error;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// ------ Schema drift detection -------- //
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftPolicy {
+    Warn,
+    Fail,
+}
+
+impl Default for DriftPolicy {
+    fn default() -> Self {
+        DriftPolicy::Warn
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Kind {
+    Integer,
+    Double,
+    Date,
+    Text,
+}
+
+// what `models::Attributes` deserializes each field into
+struct Expected {
+    name: &'static str,
+    kind: Kind,
+    required: bool,
+}
+
+const fn exp(name: &'static str, kind: Kind, required: bool) -> Expected {
+    Expected {
+        name,
+        kind,
+        required,
+    }
+}
+
+const PERKEMBANGAN_FIELDS: &[Expected] = &[
+    exp("Hari_ke", Kind::Integer, true),
+    exp("Tanggal", Kind::Date, true),
+    exp("Jumlah_Kasus_Baru_per_Hari", Kind::Integer, true),
+    exp("Jumlah_Kasus_Kumulatif", Kind::Integer, true),
+    exp("Jumlah_pasien_dalam_perawatan", Kind::Integer, true),
+    exp("Persentase_Pasien_dalam_Perawatan", Kind::Double, true),
+    exp("Jumlah_Pasien_Sembuh", Kind::Integer, true),
+    exp("Persentase_Pasien_Sembuh", Kind::Double, true),
+    exp("Jumlah_Pasien_Meninggal", Kind::Integer, true),
+    exp("Persentase_Pasien_Meninggal", Kind::Double, true),
+    exp("Jumlah_Kasus_Sembuh_per_Hari", Kind::Integer, true),
+    exp("Jumlah_Kasus_Meninggal_per_Hari", Kind::Integer, true),
+    exp("Jumlah_Kasus_Dirawat_per_Hari", Kind::Integer, true),
+    exp("Kasus_Sedang_Investigasi_Lapangan", Kind::Integer, false),
+    exp("Pembaruan_Terakhir", Kind::Date, true),
+];
+
+const PERPROV_FIELDS: &[Expected] = &[
+    exp("FID", Kind::Integer, false),
+    exp("Kode_Provi", Kind::Integer, true),
+    exp("Provinsi", Kind::Text, true),
+    exp("Kasus_Posi", Kind::Integer, true),
+    exp("Kasus_Semb", Kind::Integer, true),
+    exp("Kasus_Meni", Kind::Integer, true),
+];
+
+// the single summed field of an `outStatistics` query
+const STATISTICS_FIELDS: &[Expected] = &[exp("value", Kind::Double, true)];
+
+// what a response is checked against
+#[derive(Copy, Clone, Debug)]
+pub enum Shape {
+    Features(Endpoint),
+    Statistics,
+}
+
+fn expected_fields(shape: Shape) -> &'static [Expected] {
+    match shape {
+        Shape::Features(Endpoint::Perprov) => PERPROV_FIELDS,
+        Shape::Features(Endpoint::Perkembangan) => PERKEMBANGAN_FIELDS,
+        Shape::Statistics => STATISTICS_FIELDS,
+    }
+}
+
+fn esri_kind(field_type: &str) -> Option<Kind> {
+    match field_type {
+        "esriFieldTypeOID"
+        | "esriFieldTypeSmallInteger"
+        | "esriFieldTypeInteger"
+        | "esriFieldTypeBigInteger" => Some(Kind::Integer),
+        "esriFieldTypeDouble" | "esriFieldTypeSingle" => Some(Kind::Double),
+        "esriFieldTypeDate" => Some(Kind::Date),
+        "esriFieldTypeString" | "esriFieldTypeGUID" | "esriFieldTypeGlobalID" => Some(Kind::Text),
+        _ => None,
+    }
+}
+
+// integers deserialize fine into f64 fields, the other way around does not
+fn compatible(expected: Kind, observed: Option<Kind>) -> bool {
+    match (expected, observed) {
+        (Kind::Double, Some(Kind::Integer)) => true,
+        (e, Some(o)) => e == o,
+        (_, None) => false,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DriftReport {
+    pub missing: Vec<String>,
+    pub missing_optional: Vec<String>,
+    pub retyped: Vec<(String, String)>,
+    pub unexpected: Vec<String>,
+}
+
+impl DriftReport {
+    // only missing required fields and type changes break deserialization
+    pub fn is_breaking(&self) -> bool {
+        !self.missing.is_empty() || !self.retyped.is_empty()
+    }
+    pub fn is_clean(&self) -> bool {
+        !self.is_breaking() && self.missing_optional.is_empty()
+    }
+}
+
+impl fmt::Display for DriftReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = vec![];
+        if !self.missing.is_empty() {
+            parts.push(format!("missing: {}", self.missing.join(", ")));
+        }
+        if !self.missing_optional.is_empty() {
+            parts.push(format!(
+                "missing optional: {}",
+                self.missing_optional.join(", ")
+            ));
+        }
+        if !self.retyped.is_empty() {
+            let retyped = self
+                .retyped
+                .iter()
+                .map(|(n, t)| format!("{} is now {}", n, t))
+                .collect::<Vec<String>>();
+            parts.push(format!("retyped: {}", retyped.join(", ")));
+        }
+        if !self.unexpected.is_empty() {
+            parts.push(format!(
+                "possibly renamed to: {}",
+                self.unexpected.join(", ")
+            ));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+pub fn check_fields(shape: Shape, fields: &[Field]) -> DriftReport {
+    let expected = expected_fields(shape);
+    let mut report = DriftReport::default();
+    for exp in expected.iter() {
+        match fields.iter().find(|f| f.name() == exp.name) {
+            Some(f) => {
+                if !compatible(exp.kind, esri_kind(f.field_type())) {
+                    report
+                        .retyped
+                        .push((exp.name.to_string(), f.field_type().to_string()));
+                }
+            }
+            None if exp.required => report.missing.push(exp.name.to_string()),
+            None => report.missing_optional.push(exp.name.to_string()),
+        }
+    }
+    // unknown fields are only interesting as rename candidates
+    if !report.missing.is_empty() || !report.missing_optional.is_empty() {
+        report.unexpected = fields
+            .iter()
+            .map(|f| f.name())
+            .filter(|n| !expected.iter().any(|exp| exp.name == *n))
+            .map(|n| n.to_string())
+            .collect();
+    }
+    report
+}
+
+// the fields the upstream returned for a dataset during a run
+pub struct ObservedSchema {
+    pub run_id: String,
+    pub dataset: String,
+    pub observed_at: DateTime<Utc>,
+    pub fields: String,
+    pub fingerprint: String,
+    pub drift: Option<String>,
+}
+
+impl ObservedSchema {
+    pub fn new(
+        run_id: &str,
+        dataset: &str,
+        fields: &[Field],
+        report: &DriftReport,
+    ) -> Result<Self, Error> {
+        let mut names = fields
+            .iter()
+            .map(|f| format!("{}:{}", f.name(), f.field_type()))
+            .collect::<Vec<String>>();
+        names.sort();
+        Ok(ObservedSchema {
+            run_id: run_id.to_string(),
+            dataset: dataset.to_string(),
+            observed_at: Utc::now(),
+            fields: serde_json::to_string(fields)?,
+            fingerprint: format!("{:x}", Sha256::digest(names.join(",").as_bytes())),
+            drift: if report.is_clean() {
+                None
+            } else {
+                Some(report.to_string())
+            },
+        })
+    }
+    pub async fn insert_db(&self, store: &dyn Store) -> Result<(), Error> {
+        store.insert_observed_schema(self).await
+    }
+}
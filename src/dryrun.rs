@@ -0,0 +1,150 @@
+use crate::models::{CovidStatistics, DataProvinsiOptVec, UpsertCounts};
+use crate::province::ProvinceIndex;
+use crate::store::{same_value, StoreTx};
+use failure::Error;
+use serde_json::{json, Value};
+
+// ------ Dry runs -------- //
+// Fetched rows are compared with the stored ones on the columns their upserts
+// compare, nothing is written.
+pub struct Diff {
+    pub counts: UpsertCounts,
+    // `+` for rows that would be inserted, `~` for the changed columns of revised ones
+    pub changes: Vec<String>,
+}
+
+fn display(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Diff {
+    fn new() -> Self {
+        Diff {
+            counts: UpsertCounts::default(),
+            changes: vec![],
+        }
+    }
+
+    async fn row(
+        &mut self,
+        tx: &mut dyn StoreTx,
+        table: &str,
+        key: &[(&str, Value)],
+        values: &[(&str, Value)],
+    ) -> Result<(), Error> {
+        let columns = values.iter().map(|(c, _)| *c).collect::<Vec<&str>>();
+        let label = key
+            .iter()
+            .map(|(_, v)| display(v))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let stored = match tx.stored_values(table, key, &columns).await? {
+            Some(s) => s,
+            None => {
+                self.counts.record(Some(true));
+                self.changes.push(format!("+ {} {}", table, label));
+                return Ok(());
+            }
+        };
+        let changed = values
+            .iter()
+            .filter_map(|(c, v)| {
+                let old = stored.get(*c).unwrap_or(&Value::Null);
+                if same_value(v, old) {
+                    None
+                } else {
+                    Some(format!("{} {} -> {}", c, old, v))
+                }
+            })
+            .collect::<Vec<String>>();
+        if changed.is_empty() {
+            self.counts.record(None);
+        } else {
+            self.counts.record(Some(false));
+            self.changes
+                .push(format!("~ {} {}: {}", table, label, changed.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+pub async fn diff_daily(tx: &mut dyn StoreTx, rows: &DataProvinsiOptVec) -> Result<Diff, Error> {
+    let mut diff = Diff::new();
+    for prov in rows.rows() {
+        diff.row(
+            tx,
+            "covid_daily",
+            &[("date", json!(prov.date))],
+            &[
+                ("day", json!(prov.day)),
+                ("new_cases_per_day", json!(prov.new_cases_per_day)),
+                ("cumulative_cases", json!(prov.cumulative_cases)),
+                ("under_treatment", json!(prov.under_treatment)),
+                (
+                    "under_treatment_per_day",
+                    json!(prov.under_treatment_per_day),
+                ),
+                (
+                    "under_treatment_percentage",
+                    json!(prov.under_treatment_percentage),
+                ),
+                ("recovered", json!(prov.recovered)),
+                ("recovered_per_day", json!(prov.recovered_per_day)),
+                ("recovered_percentage", json!(prov.recovered_percentage)),
+                ("deaths", json!(prov.deaths)),
+                ("deaths_per_day", json!(prov.deaths_per_day)),
+                ("deaths_percentage", json!(prov.deaths_percentage)),
+            ],
+        )
+        .await?;
+    }
+    Ok(diff)
+}
+
+// rows are resolved against the province table as they would be when stored
+pub async fn diff_province(tx: &mut dyn StoreTx, rows: &DataProvinsiOptVec) -> Result<Diff, Error> {
+    let mut diff = Diff::new();
+    let provinces = ProvinceIndex::load(tx).await?;
+    for (prov, province_id, _) in rows.resolve_provinces(&provinces, &mut diff.counts) {
+        let province_id = match province_id {
+            Some(id) => id,
+            None => continue,
+        };
+        diff.row(
+            tx,
+            "covid_province",
+            &[
+                ("province_id", json!(province_id)),
+                ("date", json!(prov.date)),
+            ],
+            &[
+                ("provinsi", json!(prov.provinsi)),
+                ("positif", json!(prov.positif)),
+                ("sembuh", json!(prov.sembuh)),
+                ("meninggal", json!(prov.meninggal)),
+            ],
+        )
+        .await?;
+    }
+    Ok(diff)
+}
+
+pub async fn diff_stats(tx: &mut dyn StoreTx, stats: &CovidStatistics) -> Result<Diff, Error> {
+    let mut diff = Diff::new();
+    diff.row(
+        tx,
+        "covid_stats",
+        &[("at_date", json!(stats.created))],
+        &[
+            ("deaths", json!(stats.deaths)),
+            ("total_cases", json!(stats.total_cases)),
+            ("recovered", json!(stats.recovered)),
+            ("pdp", json!(stats.pdp)),
+        ],
+    )
+    .await?;
+    Ok(diff)
+}
@@ -1,15 +1,29 @@
+use crate::archive::Archiver;
+use crate::config::Config;
+use crate::drift::{check_fields, DriftPolicy, ObservedSchema, Shape};
+use crate::dryrun;
+use crate::export;
 use crate::helpers::{
-    add_cur_date, create_statistics_query, ep_val, Endpoint, QueryParams, WhereQueries,
+    create_statistics_query, ep_val, reporting_day, Endpoint, EndpointsConfig, QueryParams,
+    WhereQueries,
 };
 use crate::models;
-use crate::queries::{build_headers, make_request_url};
-use crate::store::PgStore;
-use chrono::Duration;
+use crate::output::OutputWriter;
+use crate::queries::{build_client, make_request_url};
+use crate::replay::Replay;
+use crate::retry::{classify_reqwest, retry_after, FetchError, RetryPolicy};
+use crate::run::{Dataset, LockPolicy, RunLog};
+use crate::store::{Store, StoreTx};
+use chrono::{Duration, NaiveDate, NaiveTime, Utc};
 use failure::Error;
 use futures::stream::{FuturesOrdered, StreamExt};
 use reqwest;
 use serde_json;
-use std::fs::File;
+use std::sync::Arc;
+use tokio::time::delay_for;
+
+// upper bound of records requested per page, the server may return less
+const PAGE_SIZE: usize = 2000;
 
 fn make_base_querystring(w: WhereQueries) -> QueryParams {
     let mut new_query = QueryParams::new();
@@ -17,18 +31,295 @@ fn make_base_querystring(w: WhereQueries) -> QueryParams {
     new_query
 }
 
+// merged result of walking every page of a FeatureServer query
+struct Paged {
+    ina_covid: models::InaCovid,
+    pages: usize,
+    records: usize,
+}
+
+// a dataset fetched and parsed, ready to be stored
+struct Batch {
+    day: NaiveDate,
+    // pages and records walked, for the paged datasets
+    paged: Option<(usize, usize)>,
+    rows: Rows,
+    // the codes the province rows were stored under, set once they are stored
+    province_ids: Vec<Option<i64>>,
+}
+
+enum Rows {
+    Daily(models::DataProvinsiOptVec),
+    Cumulative(models::CovidStatistics),
+    Province(models::DataProvinsiOptVec),
+}
+
+impl Batch {
+    fn dataset(&self) -> Dataset {
+        match self.rows {
+            Rows::Daily(_) => Dataset::Daily,
+            Rows::Cumulative(_) => Dataset::Cumulative,
+            Rows::Province(_) => Dataset::Province,
+        }
+    }
+}
+
 pub struct Request {
     client: reqwest::Client,
-    pgstore: PgStore,
-    jsondir: String,
+    store: Arc<dyn Store>,
+    output: OutputWriter,
+    retry: RetryPolicy,
+    endpoints: EndpointsConfig,
+    archiver: Option<Archiver>,
+    replay: Option<Replay>,
+    drift: DriftPolicy,
+    run_id: String,
+    publish_cutoff: NaiveTime,
+    run_lock: LockPolicy,
+    bulk_threshold: usize,
+    dry_run: bool,
+    // exports to after each successful run
+    parquet_dir: Option<String>,
+    // for the GeoJSON output
+    province_geometry: bool,
 }
 
 impl Request {
-    pub fn new(store: PgStore, jsondir: String) -> Self {
-        Request {
-            client: reqwest::Client::new(),
-            pgstore: store,
-            jsondir: jsondir,
+    pub fn new(
+        store: Arc<dyn Store>,
+        config: &Config,
+        replay: Option<Replay>,
+        dry_run: bool,
+    ) -> Result<Self, Error> {
+        // replayed responses are archived already, dry runs write nothing
+        let archiver = if config.archive.enabled && replay.is_none() && !dry_run {
+            Some(Archiver::new(config.json_dir().as_str(), &config.archive)?)
+        } else {
+            None
+        };
+        let mut run_id = format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%dT%H%M%SZ"),
+            std::process::id()
+        );
+        // several archived days are replayed within the same second
+        if let Some(day) = replay.as_ref().and_then(|r| r.day()) {
+            run_id = format!("{}-{}", run_id, day.format("%Y%m%d"));
+        }
+        Ok(Request {
+            client: build_client(&config.http)?,
+            store: store,
+            output: OutputWriter::new(
+                config.json_dir().as_str(),
+                &config.json_output,
+                &config.output_formats,
+            )?,
+            province_geometry: config.output_formats.province_geometry(),
+            retry: config.retry.clone(),
+            endpoints: config.endpoints.clone(),
+            archiver: archiver,
+            replay: replay,
+            drift: config.schema_drift,
+            run_id: run_id,
+            publish_cutoff: config.publish_cutoff,
+            run_lock: config.run_lock,
+            bulk_threshold: config.bulk_threshold,
+            dry_run: dry_run,
+            parquet_dir: if config.parquet.after_run {
+                Some(config.parquet_dir())
+            } else {
+                None
+            },
+        })
+    }
+
+    pub fn run_id(&self) -> &str {
+        self.run_id.as_str()
+    }
+
+    // ingests every dataset under one run id, logged in ingestion_runs and
+    // serialized with other processes through the run lock. When `atomic`, the
+    // datasets share one transaction and are committed together.
+    pub async fn run(&self, atomic: bool) -> Result<Vec<String>, Error> {
+        let lock = match self.store.lock_run(self.run_id(), self.run_lock).await? {
+            Some(lock) => lock,
+            None => {
+                return Ok(vec![
+                    "Ingestion skipped, another run is in progress".to_string()
+                ])
+            }
+        };
+        let result = self.run_logged(atomic).await;
+        // the outcome of the run matters more than a failed release
+        if let Err(e) = lock.release().await {
+            eprintln!("Could not release the run lock: {}", e);
+        }
+        result
+    }
+
+    // fetches and validates every dataset like `run`, then reports how the stored
+    // rows would change instead of storing them. No lock is taken and the run
+    // isn't logged.
+    pub async fn dry_run(&self) -> Result<Vec<String>, Error> {
+        let mut batches = vec![];
+        for dataset in Dataset::ALL.iter() {
+            batches.push(self.fetch(*dataset).await?);
+        }
+        // only read from, dropped without a commit
+        let mut tx = self.store.begin_run(self.run_id()).await?;
+        let mut messages = vec![];
+        for batch in batches.iter() {
+            messages.push(self.diff_batch(batch, tx.as_mut()).await?);
+        }
+        Ok(messages)
+    }
+
+    async fn run_logged(&self, atomic: bool) -> Result<Vec<String>, Error> {
+        let mut log = RunLog::start(self.store.as_ref(), self.run_id(), atomic).await?;
+        let result = self.run_datasets(atomic, &mut log).await;
+        // failing to log the run must not hide how the run itself went
+        if let Err(e) = log.finish(self.store.as_ref(), result.as_ref().err()).await {
+            eprintln!("Could not record the ingestion run: {}", e);
+        }
+        result
+    }
+
+    // datasets are fetched before their transaction begins, so it never spans network calls
+    async fn run_datasets(&self, atomic: bool, log: &mut RunLog) -> Result<Vec<String>, Error> {
+        let mut messages = vec![];
+        let mut written = vec![];
+        if atomic {
+            let mut batches = vec![];
+            for dataset in Dataset::ALL.iter() {
+                batches.push(self.fetch(*dataset).await?);
+            }
+            let mut tx = self.store.begin_run(self.run_id()).await?;
+            let mut done = vec![];
+            for batch in batches.iter_mut() {
+                done.push((batch.dataset(), self.store_batch(batch, tx.as_mut()).await?));
+            }
+            tx.commit().await?;
+            // committed counts are logged even if writing the files fails
+            for (dataset, (message, counts)) in done {
+                log.record(dataset, counts);
+                messages.push(message);
+            }
+            // nothing is written to disk until every dataset is committed
+            for batch in batches.iter() {
+                self.write_batch(batch)?;
+            }
+            written.extend(batches.iter().map(|b| (b.dataset(), b.day)));
+        } else {
+            for dataset in Dataset::ALL.iter() {
+                let mut batch = self.fetch(*dataset).await?;
+                let mut tx = self.store.begin_run(self.run_id()).await?;
+                let (message, counts) = self.store_batch(&mut batch, tx.as_mut()).await?;
+                tx.commit().await?;
+                log.record(*dataset, counts);
+                messages.push(message);
+                self.write_batch(&batch)?;
+                written.push((*dataset, batch.day));
+            }
+        }
+        // the aliases only move once every dataset of the run is stored, and
+        // replays of past days leave them at the current data
+        let today = reporting_day(Utc::now(), self.publish_cutoff);
+        for (dataset, day) in written {
+            if day >= today {
+                self.output.publish_latest(dataset, day)?;
+            }
+        }
+        if let Some(dir) = &self.parquet_dir {
+            messages.extend(export::parquet(self.store.as_ref(), dir.as_str()).await?);
+        }
+        Ok(messages)
+    }
+
+    async fn fetch(&self, dataset: Dataset) -> Result<Batch, Error> {
+        match dataset {
+            Dataset::Daily => self.fetch_daily().await,
+            Dataset::Cumulative => self.cumulative_stats(0).await,
+            Dataset::Province => self.fetch_province().await,
+        }
+    }
+
+    // upserts a fetched dataset, its output files are written once it is committed
+    async fn store_batch(
+        &self,
+        batch: &mut Batch,
+        tx: &mut dyn StoreTx,
+    ) -> Result<(String, models::UpsertCounts), Error> {
+        let (label, counts) = match &batch.rows {
+            Rows::Daily(v) => ("Daily", v.insert_db_daily(tx, self.bulk_threshold).await?),
+            Rows::Cumulative(s) => ("Cumulative", s.insert_db(tx).await?),
+            Rows::Province(v) => {
+                let (counts, ids) = v.insert_db_province(tx, self.bulk_threshold).await?;
+                batch.province_ids = ids;
+                ("Province", counts)
+            }
+        };
+        let message = match batch.paged {
+            Some((pages, records)) => format!(
+                "{} stats succesfully stored ({} pages, {} records; {})",
+                label, pages, records, counts
+            ),
+            None => format!("{} stats succesfully stored ({})", label, counts),
+        };
+        Ok((message, counts))
+    }
+
+    // the dated output files of a stored dataset
+    fn write_batch(&self, batch: &Batch) -> Result<(), Error> {
+        match &batch.rows {
+            Rows::Daily(v) => self.output.write(
+                batch.dataset(),
+                batch.day,
+                v,
+                models::DAILY_COLUMNS,
+                &v.daily_records(),
+                &[],
+            ),
+            Rows::Cumulative(s) => self.output.write(
+                batch.dataset(),
+                batch.day,
+                s,
+                models::STATS_COLUMNS,
+                &s.records(),
+                &[],
+            ),
+            Rows::Province(v) => self.output.write(
+                batch.dataset(),
+                batch.day,
+                v,
+                models::PROVINCE_COLUMNS,
+                &v.province_records(&batch.province_ids),
+                &v.province_features(&batch.province_ids),
+            ),
+        }
+    }
+
+    async fn diff_batch(&self, batch: &Batch, tx: &mut dyn StoreTx) -> Result<String, Error> {
+        let (label, diff) = match &batch.rows {
+            Rows::Daily(v) => ("Daily", dryrun::diff_daily(tx, v).await?),
+            Rows::Cumulative(s) => ("Cumulative", dryrun::diff_stats(tx, s).await?),
+            Rows::Province(v) => ("Province", dryrun::diff_province(tx, v).await?),
+        };
+        let mut lines = vec![match batch.paged {
+            Some((pages, records)) => format!(
+                "{} stats would be stored ({} pages, {} records; {})",
+                label, pages, records, diff.counts
+            ),
+            None => format!("{} stats would be stored ({})", label, diff.counts),
+        }];
+        lines.extend(diff.changes.iter().map(|c| format!("  {}", c)));
+        Ok(lines.join("\n"))
+    }
+
+    // replayed archives are stored as of the day they were fetched
+    fn reporting_day(&self) -> NaiveDate {
+        match self.replay.as_ref().and_then(|r| r.day()) {
+            Some(day) => day,
+            None => reporting_day(Utc::now(), self.publish_cutoff),
         }
     }
     async fn fetch_common(
@@ -36,20 +327,119 @@ impl Request {
         w: WhereQueries,
         stat: Vec<(&str, &str)>,
         endpoint: Endpoint,
+        dataset: &str,
     ) -> Result<String, Error> {
-        let base_query = make_base_querystring(w);
+        if let Some(replay) = &self.replay {
+            return replay.load(dataset);
+        }
+        let mut base_query = make_base_querystring(w);
+        if let Endpoint::Perprov = endpoint {
+            base_query.return_geometry = self.province_geometry;
+        }
         let query_string = base_query.add_queries(stat);
-        let req_url = make_request_url(ep_val(endpoint).as_str(), query_string.as_str());
-        match req_url {
-            Ok(u) => {
-                let resp = self.fetcher(u);
-                match resp.await {
-                    Ok(r) => Ok(r.text().await?),
-                    Err(e) => return Err(e),
+        let req_url = make_request_url(
+            ep_val(endpoint, &self.endpoints).as_str(),
+            query_string.as_str(),
+        )?;
+        let body = self.fetcher(req_url.clone()).await?;
+        if let Some(archiver) = &self.archiver {
+            let entry = archiver.store(dataset, req_url.as_str(), body.as_str())?;
+            entry.insert_db(self.store.as_ref()).await?;
+        }
+        Ok(body)
+    }
+
+    async fn fetch_paged(
+        &self,
+        w: WhereQueries,
+        order_by: &str,
+        endpoint: Endpoint,
+        dataset: &str,
+    ) -> Result<Paged, Error> {
+        let page_size = PAGE_SIZE.to_string();
+        let mut merged: Option<models::InaCovid> = None;
+        let mut pages = 0;
+        let mut offset = 0;
+        loop {
+            let offset_str = offset.to_string();
+            let page_name = format!("{}-p{}", dataset, pages);
+            let results = self
+                .fetch_common(
+                    w,
+                    vec![
+                        ("orderByFields", order_by),
+                        ("resultRecordCount", page_size.as_str()),
+                        ("resultOffset", offset_str.as_str()),
+                    ],
+                    endpoint,
+                    page_name.as_str(),
+                )
+                .await?;
+            let page: models::InaCovid = match serde_json::from_str(results.as_str()) {
+                Ok(ic) => ic,
+                Err(e) => return Err(format_err!("Failed to serialize InaCovid json: {}", e)),
+            };
+            pages += 1;
+            let count = page.feature_count();
+            let exceeded = page.exceeded_transfer_limit();
+            merged = match merged.take() {
+                Some(mut m) => {
+                    m.merge(page);
+                    Some(m)
                 }
+                None => Some(page),
+            };
+            // an empty page while the flag is still set would loop forever
+            if !exceeded || count == 0 {
+                break;
+            }
+            offset += count;
+        }
+        let ina_covid = merged.expect("At least one page is fetched");
+        self.check_schema(Shape::Features(endpoint), dataset, ina_covid.get_fields())
+            .await?;
+        let records = ina_covid.feature_count();
+        Ok(Paged {
+            ina_covid,
+            pages,
+            records,
+        })
+    }
+
+    // compares the returned fields against what `models::Attributes` expects
+    async fn check_schema(
+        &self,
+        shape: Shape,
+        dataset: &str,
+        fields: Option<&[models::Field]>,
+    ) -> Result<(), Error> {
+        let fields = match fields {
+            Some(f) => f,
+            None if self.drift == DriftPolicy::Fail => {
+                return Err(format_err!("No field metadata in {} data", dataset))
+            }
+            None => {
+                eprintln!(
+                    "No field metadata in {} data, schema drift is not checked",
+                    dataset
+                );
+                return Ok(());
             }
-            Err(e) => return Err(e),
+        };
+        let report = check_fields(shape, fields);
+        if !self.dry_run {
+            ObservedSchema::new(self.run_id(), dataset, fields, &report)?
+                .insert_db(self.store.as_ref())
+                .await?;
+        }
+        if report.is_clean() {
+            return Ok(());
+        }
+        if report.is_breaking() && self.drift == DriftPolicy::Fail {
+            return Err(format_err!("Schema drift in {} data: {}", dataset, report));
         }
+        eprintln!("Schema drift in {} data: {}", dataset, report);
+        Ok(())
     }
 
     fn get_json_val(&self, res: Result<String, Error>) -> Result<serde_json::Value, Error> {
@@ -62,94 +452,116 @@ impl Request {
         }
     }
 
-    async fn fetcher(&self, url: String) -> Result<reqwest::Response, Error> {
+    // sends the request, retrying transient failures according to the retry policy
+    async fn fetcher(&self, url: String) -> Result<String, Error> {
+        let max_attempts = self.retry.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let (err, wait) = match self.try_fetch(url.as_str()).await {
+                Ok(body) => {
+                    if attempt > 1 {
+                        println!(
+                            "Fetch attempt {}/{} succeeded: {}",
+                            attempt, max_attempts, url
+                        );
+                    }
+                    return Ok(body);
+                }
+                Err(FetchError::Fatal(e)) => {
+                    eprintln!(
+                        "Fetch attempt {}/{} failed permanently: {}: {}",
+                        attempt, max_attempts, url, e
+                    );
+                    return Err(e);
+                }
+                Err(FetchError::Transient(e, wait)) => (e, wait),
+            };
+            if attempt >= max_attempts {
+                eprintln!(
+                    "Fetch attempt {}/{} failed, giving up: {}: {}",
+                    attempt, max_attempts, url, err
+                );
+                return Err(err);
+            }
+            let delay = self.retry.delay(attempt, wait);
+            eprintln!(
+                "Fetch attempt {}/{} failed, retrying in {}ms: {}: {}",
+                attempt,
+                max_attempts,
+                delay.as_millis(),
+                url,
+                err
+            );
+            delay_for(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn try_fetch(&self, url: &str) -> Result<String, FetchError> {
         let resp = self
             .client
-            .get(url.as_str())
-            .headers(build_headers())
+            .get(url)
             .send()
-            .await?;
-        Ok(resp)
+            .await
+            .map_err(classify_reqwest)?;
+        let status = resp.status();
+        if !status.is_success() {
+            let err = format_err!("Upstream responded with {}", status);
+            if self.retry.is_retryable_status(status.as_u16()) {
+                return Err(FetchError::Transient(err, retry_after(resp.headers())));
+            }
+            return Err(FetchError::Fatal(err));
+        }
+        resp.text().await.map_err(classify_reqwest)
     }
 
-    fn get_ina_covid_vec(&self, res: models::InaCovid) -> models::DataProvinsiOptVec {
+    fn get_ina_covid_vec(
+        &self,
+        res: models::InaCovid,
+        day: NaiveDate,
+    ) -> models::DataProvinsiOptVec {
         let data_prov = res
             .get_features()
             .into_iter()
-            .map(|x| models::DataProvinsiOpt::new(x))
+            .map(|x| models::DataProvinsiOpt::new(x, day))
             .collect::<Vec<models::DataProvinsiOpt>>();
         models::DataProvinsiOptVec::new(&data_prov)
     }
 
-    fn set_json_filename(&self, name: &str) -> String {
-        format!(
-            "{}{}-{}.json",
-            self.jsondir.as_str(),
-            name,
-            add_cur_date(Duration::seconds(0))
-        )
-    }
-
-    pub async fn fetch_daily(&self) -> Result<String, Error> {
-        let results = self
-            .fetch_common(
-                WhereQueries::BeforeToday(0),
-                vec![
-                    ("orderByFields", "Tanggal asc"),
-                    ("resultRecordCount", "2000"),
-                    ("resultOffset", "0"),
-                ],
+    async fn fetch_daily(&self) -> Result<Batch, Error> {
+        let day = self.reporting_day();
+        let paged = self
+            .fetch_paged(
+                WhereQueries::BeforeToday(day),
+                "Tanggal asc",
                 Endpoint::Perkembangan,
+                "daily",
             )
             .await?;
-        let ina_covid: Result<models::InaCovid, serde_json::Error> =
-            serde_json::from_str(results.as_str());
-        match ina_covid {
-            Ok(ic) => {
-                let ina_covid_vec = self.get_ina_covid_vec(ic);
-                ina_covid_vec.insert_db_daily(&self.pgstore).await?;
-                match serde_json::to_string_pretty(&ina_covid_vec) {
-                    Ok(d) => {
-                        let json_file = self.set_json_filename("daily");
-                        serde_json::to_writer(&File::create(json_file.as_str())?, &d)?;
-                        Ok("Daily stats succesfully stored".to_string())
-                    }
-                    Err(e) => Err(format_err!("Failed to produce json {}", e)),
-                }
-            }
-            Err(e) => return Err(format_err!("Failed to serialize InaCovid json: {}", e)),
-        }
+        Ok(Batch {
+            day,
+            paged: Some((paged.pages, paged.records)),
+            rows: Rows::Daily(self.get_ina_covid_vec(paged.ina_covid, day)),
+            province_ids: vec![],
+        })
     }
 
-    pub async fn fetch_province(&self) -> Result<String, Error> {
-        let results = self
-            .fetch_common(
+    async fn fetch_province(&self) -> Result<Batch, Error> {
+        let day = self.reporting_day();
+        let paged = self
+            .fetch_paged(
                 WhereQueries::All,
-                vec![
-                    ("orderByFields", "Kasus_Posi desc"),
-                    ("resultRecordCount", "2000"),
-                    ("resultOffset", "0"),
-                ],
+                "Kasus_Posi desc",
                 Endpoint::Perprov,
+                "province",
             )
             .await?;
-        let ina_covid: Result<models::InaCovid, serde_json::Error> =
-            serde_json::from_str(results.as_str());
-        match ina_covid {
-            Ok(ic) => {
-                let ina_covid_vec = self.get_ina_covid_vec(ic);
-                ina_covid_vec.insert_db_province(&self.pgstore).await?;
-                match serde_json::to_string_pretty(&ina_covid_vec) {
-                    Ok(d) => {
-                        let json_file = self.set_json_filename("province");
-                        serde_json::to_writer(&File::create(json_file.as_str())?, &d)?;
-                        Ok("Province stats succesfully stored".to_string())
-                    }
-                    Err(e) => Err(format_err!("Failed to produce json {}", e)),
-                }
-            }
-            Err(e) => return Err(format_err!("Failed to serialize InaCovid json: {}", e)),
-        }
+        Ok(Batch {
+            day,
+            paged: Some((paged.pages, paged.records)),
+            rows: Rows::Province(self.get_ina_covid_vec(paged.ina_covid, day)),
+            province_ids: vec![],
+        })
     }
 
     // -- Disabled --
@@ -158,22 +570,28 @@ impl Request {
     //     Ok("Surveillance cases fetched".to_string())
     // }
 
-    pub async fn cumulative_stats(&self, prefix: i64) -> Result<String, Error> {
+    // `prefix` shifts the reporting day, e.g. -1 for the figures of the day before
+    async fn cumulative_stats(&self, prefix: i64) -> Result<Batch, Error> {
+        let day = self.reporting_day() + Duration::days(prefix);
         // all stats
         let stats_arr = [
-            create_statistics_query("Jumlah_Pasien_Meninggal"),
-            create_statistics_query("Jumlah_Pasien_Sembuh"),
-            create_statistics_query("Jumlah_pasien_dalam_perawatan"),
-            create_statistics_query("Jumlah_Kasus_Kumulatif"),
-        ];
+            "Jumlah_Pasien_Meninggal",
+            "Jumlah_Pasien_Sembuh",
+            "Jumlah_pasien_dalam_perawatan",
+            "Jumlah_Kasus_Kumulatif",
+        ]
+        .iter()
+        .map(|f| (format!("cumulative-{}", f), create_statistics_query(f)))
+        .collect::<Vec<(String, String)>>();
         // fetch each results, and puts it into owned vector
         let all_stats = stats_arr
             .iter()
-            .map(|x| {
+            .map(|(name, x)| {
                 self.fetch_common(
-                    WhereQueries::CurrentDate(prefix),
+                    WhereQueries::CurrentDate(day),
                     vec![("outStatistics", x.as_str())],
                     Endpoint::Perkembangan,
+                    name.as_str(),
                 )
             })
             .collect::<FuturesOrdered<_>>()
@@ -182,27 +600,30 @@ impl Request {
             .into_iter()
             .map(|i| self.get_json_val(i))
             .collect::<Vec<Result<serde_json::Value, Error>>>();
+        for ((name, _), stat) in stats_arr.iter().zip(all_stats.iter()) {
+            if let Ok(v) = stat {
+                let fields = match v.get("fields") {
+                    Some(f) => Some(serde_json::from_value::<Vec<models::Field>>(f.clone())?),
+                    None => None,
+                };
+                self.check_schema(Shape::Statistics, name.as_str(), fields.as_deref())
+                    .await?;
+            }
+        }
         // use slice patterns to produce json
         match &all_stats[..] {
-            [Ok(d), Ok(r), Ok(p), Ok(c)] => {
-                let created = add_cur_date(Duration::seconds(0));
-                let new_stats = models::CovidStatistics::new(
+            [Ok(d), Ok(r), Ok(p), Ok(c)] => Ok(Batch {
+                day,
+                paged: None,
+                rows: Rows::Cumulative(models::CovidStatistics::new(
                     c["features"][0]["attributes"]["value"].as_i64(),
                     d["features"][0]["attributes"]["value"].as_i64(),
                     r["features"][0]["attributes"]["value"].as_i64(),
                     p["features"][0]["attributes"]["value"].as_i64(),
-                    Some(created),
-                );
-                new_stats.insert_db(&self.pgstore).await?;
-                match serde_json::to_string_pretty(&new_stats) {
-                    Ok(d) => {
-                        let json_file = self.set_json_filename("cumulative");
-                        serde_json::to_writer(&File::create(json_file.as_str())?, &d)?;
-                        Ok("Cumulative stats succesfully stored".to_string())
-                    }
-                    Err(e) => return Err(format_err!("Failure to produce json value {}", e)),
-                }
-            }
+                    Some(day),
+                )),
+                province_ids: vec![],
+            }),
             _ => return Err(format_err!("Failure to get cumulative stats")),
         }
     }
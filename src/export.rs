@@ -0,0 +1,250 @@
+use crate::store::Store;
+use chrono::{DateTime, NaiveDate};
+use failure::Error;
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, rename, File};
+use std::sync::Arc;
+
+// ------ Parquet export config -------- //
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParquetConfig {
+    // exports after every successful ingestion, besides `inacovid export`
+    #[serde(rename = "afterRun")]
+    pub after_run: bool,
+    // defaults to `<jsonOutputDir>parquet/`
+    pub dir: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Kind {
+    Date,
+    Timestamp,
+    Int64,
+    Double,
+    Text,
+    Bool,
+}
+
+struct Column {
+    name: &'static str,
+    kind: Kind,
+}
+
+const fn col(name: &'static str, kind: Kind) -> Column {
+    Column { name, kind }
+}
+
+// the first column is the date the rows are partitioned on
+struct Table {
+    name: &'static str,
+    order_by: &'static str,
+    columns: &'static [Column],
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "covid_daily",
+        order_by: "date",
+        columns: &[
+            col("date", Kind::Date),
+            col("day", Kind::Int64),
+            col("new_cases_per_day", Kind::Int64),
+            col("cumulative_cases", Kind::Int64),
+            col("under_treatment", Kind::Int64),
+            col("under_treatment_per_day", Kind::Int64),
+            col("under_treatment_percentage", Kind::Double),
+            col("recovered", Kind::Int64),
+            col("recovered_per_day", Kind::Int64),
+            col("recovered_percentage", Kind::Double),
+            col("deaths", Kind::Int64),
+            col("deaths_per_day", Kind::Int64),
+            col("deaths_percentage", Kind::Double),
+            col("latest_update", Kind::Timestamp),
+        ],
+    },
+    Table {
+        name: "covid_province",
+        order_by: "date, province_id",
+        columns: &[
+            col("date", Kind::Date),
+            col("province_id", Kind::Int64),
+            col("provinsi", Kind::Text),
+            col("positif", Kind::Int64),
+            col("sembuh", Kind::Int64),
+            col("meninggal", Kind::Int64),
+            col("unknown_province", Kind::Bool),
+        ],
+    },
+];
+
+fn message_type(table: &Table) -> String {
+    let fields = table
+        .columns
+        .iter()
+        .map(|c| match c.kind {
+            Kind::Date => format!("OPTIONAL INT32 {} (DATE);", c.name),
+            Kind::Timestamp => format!("OPTIONAL INT64 {} (TIMESTAMP_MILLIS);", c.name),
+            Kind::Int64 => format!("OPTIONAL INT64 {};", c.name),
+            Kind::Double => format!("OPTIONAL DOUBLE {};", c.name),
+            Kind::Text => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", c.name),
+            Kind::Bool => format!("OPTIONAL BOOLEAN {};", c.name),
+        })
+        .collect::<Vec<String>>();
+    format!("message {} {{ {} }}", table.name, fields.join(" "))
+}
+
+// parquet dates are days since the unix epoch
+fn parse_date(v: &Value) -> Result<Option<NaiveDate>, Error> {
+    match v.as_str() {
+        Some(s) => Ok(Some(NaiveDate::parse_from_str(s, "%Y-%m-%d")?)),
+        None => Ok(None),
+    }
+}
+
+fn epoch_days(d: NaiveDate) -> i32 {
+    d.signed_duration_since(NaiveDate::from_ymd(1970, 1, 1))
+        .num_days() as i32
+}
+
+fn epoch_millis(v: &Value) -> Result<Option<i64>, Error> {
+    match v.as_str() {
+        Some(s) => Ok(Some(DateTime::parse_from_rfc3339(s)?.timestamp_millis())),
+        None => Ok(None),
+    }
+}
+
+// sqlite hands booleans back as 0 and 1
+fn as_bool(v: &Value) -> Option<bool> {
+    v.as_bool().or_else(|| v.as_i64().map(|i| i != 0))
+}
+
+// values present at each row, null values only get a definition level of 0
+fn levels<T>(values: &[Option<T>]) -> (Vec<T>, Vec<i16>)
+where
+    T: Clone,
+{
+    let present = values.iter().flatten().cloned().collect::<Vec<T>>();
+    let defs = values
+        .iter()
+        .map(|v| if v.is_some() { 1 } else { 0 })
+        .collect::<Vec<i16>>();
+    (present, defs)
+}
+
+// written next to the target and renamed, like the JSON outputs
+fn write_partition(path: &str, table: &Table, rows: &[&Value]) -> Result<(), Error> {
+    let schema = Arc::new(parse_message_type(message_type(table).as_str())?);
+    let props = Arc::new(WriterProperties::builder().build());
+    let tmp = format!("{}.tmp-{}", path, std::process::id());
+    let mut writer = SerializedFileWriter::new(File::create(tmp.as_str())?, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+    for column in table.columns.iter() {
+        let values = rows
+            .iter()
+            .map(|r| r.get(column.name).unwrap_or(&Value::Null))
+            .collect::<Vec<&Value>>();
+        let mut col_writer = match row_group.next_column()? {
+            Some(c) => c,
+            None => return Err(format_err!("No parquet column left for {}", column.name)),
+        };
+        match (column.kind, col_writer.untyped()) {
+            (Kind::Date, ColumnWriter::Int32ColumnWriter(w)) => {
+                let mut days = vec![];
+                for v in values.iter() {
+                    days.push(parse_date(v)?.map(epoch_days));
+                }
+                let (present, defs) = levels(&days);
+                w.write_batch(&present, Some(&defs), None)?;
+            }
+            (Kind::Timestamp, ColumnWriter::Int64ColumnWriter(w)) => {
+                let mut millis = vec![];
+                for v in values.iter() {
+                    millis.push(epoch_millis(v)?);
+                }
+                let (present, defs) = levels(&millis);
+                w.write_batch(&present, Some(&defs), None)?;
+            }
+            (Kind::Int64, ColumnWriter::Int64ColumnWriter(w)) => {
+                let ints = values.iter().map(|v| v.as_i64()).collect::<Vec<_>>();
+                let (present, defs) = levels(&ints);
+                w.write_batch(&present, Some(&defs), None)?;
+            }
+            (Kind::Double, ColumnWriter::DoubleColumnWriter(w)) => {
+                let doubles = values.iter().map(|v| v.as_f64()).collect::<Vec<_>>();
+                let (present, defs) = levels(&doubles);
+                w.write_batch(&present, Some(&defs), None)?;
+            }
+            (Kind::Text, ColumnWriter::ByteArrayColumnWriter(w)) => {
+                let texts = values
+                    .iter()
+                    .map(|v| v.as_str().map(ByteArray::from))
+                    .collect::<Vec<_>>();
+                let (present, defs) = levels(&texts);
+                w.write_batch(&present, Some(&defs), None)?;
+            }
+            (Kind::Bool, ColumnWriter::BoolColumnWriter(w)) => {
+                let bools = values.iter().map(|v| as_bool(v)).collect::<Vec<_>>();
+                let (present, defs) = levels(&bools);
+                w.write_batch(&present, Some(&defs), None)?;
+            }
+            (kind, _) => {
+                return Err(format_err!(
+                    "Unexpected parquet column type for {} ({:?})",
+                    column.name,
+                    kind
+                ))
+            }
+        }
+        col_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    rename(tmp.as_str(), path)?;
+    Ok(())
+}
+
+// ------ Parquet export -------- //
+// Writes the full history of each table as `<dir><table>/month=YYYY-MM/part-0.parquet`,
+// replacing the previous export of every month.
+pub async fn parquet(store: &dyn Store, dir: &str) -> Result<Vec<String>, Error> {
+    let mut messages = vec![];
+    for table in TABLES.iter() {
+        let names = table.columns.iter().map(|c| c.name).collect::<Vec<&str>>();
+        let rows = store
+            .table_rows(table.name, names.as_slice(), table.order_by)
+            .await?;
+        let mut months: BTreeMap<String, Vec<&Value>> = BTreeMap::new();
+        for row in rows.iter() {
+            let month = match parse_date(row.get(table.columns[0].name).unwrap_or(&Value::Null))? {
+                Some(d) => d.format("%Y-%m").to_string(),
+                None => continue,
+            };
+            months.entry(month).or_insert_with(Vec::new).push(row);
+        }
+        for (month, month_rows) in months.iter() {
+            let partition = format!("{}{}/month={}/", dir, table.name, month);
+            create_dir_all(partition.as_str())?;
+            write_partition(
+                format!("{}part-0.parquet", partition).as_str(),
+                table,
+                month_rows,
+            )?;
+        }
+        messages.push(format!(
+            "Exported {} {} rows in {} monthly partitions to {}{}/",
+            rows.len(),
+            table.name,
+            months.len(),
+            dir,
+            table.name
+        ));
+    }
+    Ok(messages)
+}
@@ -1,17 +1,34 @@
-use chrono::{Duration, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
 use qstring::QString;
 
 // ------- Endpoint helpers -------- //
+#[derive(Copy, Clone, Debug)]
 pub enum Endpoint {
     Perprov,
     Perkembangan,
     // Surveillance,
 }
 
-pub fn ep_val(e: Endpoint) -> String {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointsConfig {
+    pub perprov: String,
+    pub perkembangan: String,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        EndpointsConfig {
+            perprov: "https://services5.arcgis.com/VS6HdKS0VfIhv8Ct/arcgis/rest/services/COVID19_Indonesia_per_Provinsi/FeatureServer/0/query".to_string(),
+            perkembangan: "https://services5.arcgis.com/VS6HdKS0VfIhv8Ct/arcgis/rest/services/Statistik_Perkembangan_COVID19_Indonesia/FeatureServer/0/query".to_string(),
+        }
+    }
+}
+
+pub fn ep_val(e: Endpoint, eps: &EndpointsConfig) -> String {
     match e {
-        Endpoint::Perprov => "https://services5.arcgis.com/VS6HdKS0VfIhv8Ct/arcgis/rest/services/COVID19_Indonesia_per_Provinsi/FeatureServer/0/query".to_string(),
-        Endpoint::Perkembangan => "https://services5.arcgis.com/VS6HdKS0VfIhv8Ct/arcgis/rest/services/Statistik_Perkembangan_COVID19_Indonesia/FeatureServer/0/query".to_string(),
+        Endpoint::Perprov => eps.perprov.clone(),
+        Endpoint::Perkembangan => eps.perkembangan.clone(),
         // Endpoint::Surveillance=> "covid-monitoring2.kemkes.go.id".to_string(),
     }
 }
@@ -23,6 +40,8 @@ pub struct QueryParams {
     out_fields: String,
     pub where_query: WhereQueries,
     spatial_rel: String,
+    // geometry comes back as WGS 84 longitude/latitude
+    pub return_geometry: bool,
 }
 
 impl QueryParams {
@@ -32,19 +51,30 @@ impl QueryParams {
             where_query: WhereQueries::All,
             out_fields: "*".to_string(),
             spatial_rel: "esriSpatialRelIntersects".to_string(),
+            return_geometry: false,
         }
     }
     // build query params
     pub fn query_params(&self) -> String {
         let wherev = where_val(self.where_query);
-        let fields: Vec<(&str, &str)> = vec![
+        let mut fields: Vec<(&str, &str)> = vec![
             ("f", self.f.as_str()),
             ("where", wherev.as_str()),
-            ("returnGeometry", "false"),
+            (
+                "returnGeometry",
+                if self.return_geometry {
+                    "true"
+                } else {
+                    "false"
+                },
+            ),
             ("spatialRel", self.spatial_rel.as_str()),
             ("outFields", self.out_fields.as_str()),
             ("cacheHint", "true"),
         ];
+        if self.return_geometry {
+            fields.push(("outSR", "4326"));
+        }
         format!("{}", QString::new(fields))
     }
 
@@ -65,8 +95,10 @@ pub enum WhereQueries {
     Recovered,
     All,
     Indonesia,
-    CurrentDate(i64),
-    BeforeToday(i64),
+    // rows of the given reporting day
+    CurrentDate(NaiveDate),
+    // rows up to and including the given reporting day
+    BeforeToday(NaiveDate),
 }
 
 pub fn where_val(w: WhereQueries) -> String {
@@ -78,22 +110,15 @@ pub fn where_val(w: WhereQueries) -> String {
         WhereQueries::Indonesia => {
             "(Provinsi = 'Indonesia') OR (Provinsi <> 'Indonesia')".to_string()
         }
-        WhereQueries::CurrentDate(prefix) => {
-            let new_prefix = count_prefix(prefix);
-            let yesterday = add_cur_date(Duration::days(new_prefix - 1));
-            let tomorrow = add_cur_date(Duration::days(new_prefix + 1));
-            let now = add_cur_date(Duration::days(new_prefix));
-            let wq = format!("(Tanggal>=timestamp '{} 17:00:00' AND Tanggal<=timestamp '{} 16:59:59' OR Tanggal>=timestamp '{} 16:59:59')",
-                             now, tomorrow, yesterday );
-            wq
-        }
-        WhereQueries::BeforeToday(prefix) => {
-            let new_prefix = count_prefix(prefix);
-            format!(
-                "Tanggal<timestamp '{} 17:00:00'",
-                add_cur_date(Duration::days(new_prefix))
-            )
-        }
+        WhereQueries::CurrentDate(day) => format!(
+            "(Tanggal>=timestamp '{}' AND Tanggal<timestamp '{}')",
+            format_timestamp(day_start(day)),
+            format_timestamp(day_start(day.succ()))
+        ),
+        WhereQueries::BeforeToday(day) => format!(
+            "Tanggal<timestamp '{}'",
+            format_timestamp(day_start(day.succ()))
+        ),
     }
 }
 
@@ -101,40 +126,144 @@ pub fn create_statistics_query(field: &str) -> String {
     format!("[{{\"statisticType\":\"sum\",\"onStatisticField\":\"{}\",\"outStatisticFieldName\":\"value\"}}]", field)
 }
 
-fn count_prefix(i: i64) -> i64 {
-    let mut prefix = i;
-    let cur_time = Utc::now().naive_utc().time();
-    let upd_time = chrono::NaiveTime::from_hms(10, 0, 0);
+// ArcGIS compares timestamp literals against the UTC value of date fields
+fn format_timestamp(d: DateTime<Utc>) -> String {
+    d.format("%Y-%m-%d %H:%M:%S").to_string()
+}
 
-    if cur_time < upd_time {
-        prefix = i - 1;
-    }
-    prefix
+// figures are reported per day in Western Indonesia Time (WIB, UTC+7, no DST)
+pub fn jakarta() -> FixedOffset {
+    FixedOffset::east(7 * 3600)
 }
 
-pub fn add_cur_date(dur: Duration) -> String {
-    let now = Utc::now();
-    let added_now = now.checked_add_signed(dur);
-    match added_now {
-        None => format_date(now.naive_utc()),
-        Some(d) => format_date(d.naive_utc()),
-    }
+pub fn jakarta_date(dt: DateTime<Utc>) -> NaiveDate {
+    dt.with_timezone(&jakarta()).naive_local().date()
 }
 
-fn format_date(d: chrono::NaiveDateTime) -> String {
-    d.format("%Y-%m-%d").to_string()
+// upstream publishes the figures of a day at this WIB time
+pub fn default_publish_cutoff() -> NaiveTime {
+    NaiveTime::from_hms(17, 0, 0)
 }
 
-fn format_date_with_time(d: chrono::NaiveDateTime) -> String {
-    d.format("%Y-%m-%d %H:%M:%S").to_string()
+// The day whose figures are the latest published ones at `now`: the WIB
+// calendar day, or the day before while its figures are not out yet.
+pub fn reporting_day(now: DateTime<Utc>, cutoff: NaiveTime) -> NaiveDate {
+    let local = now.with_timezone(&jakarta()).naive_local();
+    if local.time() < cutoff {
+        local.date().pred()
+    } else {
+        local.date()
+    }
+}
+
+// midnight WIB of a reporting day, which is how upstream stamps `Tanggal`
+pub fn day_start(day: NaiveDate) -> DateTime<Utc> {
+    jakarta()
+        .from_local_datetime(&day.and_hms(0, 0, 0))
+        .single()
+        .expect("WIB has no DST gaps")
+        .with_timezone(&Utc)
 }
 
-pub fn timestamp_to_date(ts: Option<i64>) -> String {
+// ArcGIS date fields are epoch milliseconds
+pub fn timestamp_to_datetime(ts: Option<i64>) -> DateTime<Utc> {
     match ts {
-        Some(secs) => {
-            let date_time = chrono::NaiveDateTime::from_timestamp(secs / 1000, 0);
-            format_date_with_time(date_time)
-        }
-        _ => format_date_with_time(Utc::now().naive_utc()),
+        Some(millis) => Utc.timestamp_millis(millis),
+        _ => Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Utc> {
+        Utc.ymd(y, m, d).and_hms(h, min, s)
+    }
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(y, m, d)
+    }
+
+    #[test]
+    fn reporting_day_around_cutoff() {
+        let cutoff = default_publish_cutoff();
+        // 17:00 WIB is 10:00 UTC
+        assert_eq!(
+            reporting_day(utc(2020, 5, 10, 9, 59, 59), cutoff),
+            day(2020, 5, 9)
+        );
+        assert_eq!(
+            reporting_day(utc(2020, 5, 10, 10, 0, 0), cutoff),
+            day(2020, 5, 10)
+        );
+        assert_eq!(
+            reporting_day(utc(2020, 5, 10, 10, 0, 1), cutoff),
+            day(2020, 5, 10)
+        );
+    }
+
+    #[test]
+    fn jakarta_day_crosses_utc_midnight() {
+        assert_eq!(jakarta_date(utc(2020, 5, 9, 16, 59, 59)), day(2020, 5, 9));
+        assert_eq!(jakarta_date(utc(2020, 5, 9, 17, 0, 0)), day(2020, 5, 10));
+        assert_eq!(jakarta_date(utc(2020, 5, 9, 23, 30, 0)), day(2020, 5, 10));
+        // still before the cutoff of the new WIB day
+        let cutoff = default_publish_cutoff();
+        assert_eq!(
+            reporting_day(utc(2020, 5, 9, 23, 30, 0), cutoff),
+            day(2020, 5, 9)
+        );
+        let midnight = NaiveTime::from_hms(0, 0, 0);
+        assert_eq!(
+            reporting_day(utc(2020, 5, 9, 23, 30, 0), midnight),
+            day(2020, 5, 10)
+        );
+    }
+
+    #[test]
+    fn reporting_day_rolls_over_month_and_year() {
+        let cutoff = default_publish_cutoff();
+        assert_eq!(jakarta_date(utc(2020, 5, 31, 17, 30, 0)), day(2020, 6, 1));
+        assert_eq!(
+            reporting_day(utc(2020, 6, 1, 3, 0, 0), cutoff),
+            day(2020, 5, 31)
+        );
+        assert_eq!(
+            reporting_day(utc(2020, 6, 1, 10, 0, 0), cutoff),
+            day(2020, 6, 1)
+        );
+        assert_eq!(jakarta_date(utc(2020, 12, 31, 17, 0, 0)), day(2021, 1, 1));
+        assert_eq!(
+            reporting_day(utc(2021, 1, 1, 9, 0, 0), cutoff),
+            day(2020, 12, 31)
+        );
+        assert_eq!(
+            reporting_day(utc(2021, 1, 1, 10, 0, 0), cutoff),
+            day(2021, 1, 1)
+        );
+    }
+
+    #[test]
+    fn day_start_is_wib_midnight() {
+        assert_eq!(day_start(day(2020, 5, 10)), utc(2020, 5, 9, 17, 0, 0));
+        assert_eq!(day_start(day(2020, 6, 1)), utc(2020, 5, 31, 17, 0, 0));
+        assert_eq!(day_start(day(2021, 1, 1)), utc(2020, 12, 31, 17, 0, 0));
+    }
+
+    #[test]
+    fn where_clauses_of_reporting_days() {
+        assert_eq!(
+            where_val(WhereQueries::CurrentDate(day(2020, 6, 1))),
+            "(Tanggal>=timestamp '2020-05-31 17:00:00' AND Tanggal<timestamp '2020-06-01 17:00:00')"
+        );
+        assert_eq!(
+            where_val(WhereQueries::CurrentDate(day(2020, 12, 31))),
+            "(Tanggal>=timestamp '2020-12-30 17:00:00' AND Tanggal<timestamp '2020-12-31 17:00:00')"
+        );
+        assert_eq!(
+            where_val(WhereQueries::BeforeToday(day(2020, 12, 31))),
+            "Tanggal<timestamp '2020-12-31 17:00:00'"
+        );
     }
 }
@@ -0,0 +1,84 @@
+use crate::store::Store;
+use chrono::{DateTime, Utc};
+use failure::Error;
+use std::fmt;
+
+// ------ Revision history of upstream corrections -------- //
+// rows are written whenever an upsert changes figures, by the record_revision()
+// trigger on Postgres and by the store itself on SQLite
+pub struct Revision {
+    table_name: String,
+    changed_at: DateTime<Utc>,
+    run_id: Option<String>,
+    old_values: serde_json::Value,
+    new_values: serde_json::Value,
+}
+
+impl Revision {
+    pub fn new(
+        table_name: String,
+        changed_at: DateTime<Utc>,
+        run_id: Option<String>,
+        old_values: serde_json::Value,
+        new_values: serde_json::Value,
+    ) -> Self {
+        Revision {
+            table_name,
+            changed_at,
+            run_id,
+            old_values,
+            new_values,
+        }
+    }
+
+    // revisions of a reporting date (`YYYY-MM-DD`) and/or a province (name or BPS code), oldest first
+    pub async fn timeline(
+        store: &dyn Store,
+        date: Option<&str>,
+        province: Option<&str>,
+    ) -> Result<Vec<Revision>, Error> {
+        store.revisions(date, province).await
+    }
+
+    // `field: old -> new` for every value the revision touched
+    fn changes(&self) -> Vec<String> {
+        let empty = serde_json::Map::new();
+        let old = self.old_values.as_object().unwrap_or(&empty);
+        let new = self.new_values.as_object().unwrap_or(&empty);
+        new.iter()
+            .filter(|(k, v)| old.get(k.as_str()) != Some(v))
+            .map(|(k, v)| {
+                let prev = old.get(k.as_str()).unwrap_or(&serde_json::Value::Null);
+                format!("{}: {} -> {}", k, prev, v)
+            })
+            .collect()
+    }
+
+    // the row the revision belongs to, e.g. its date and province
+    fn subject(&self) -> String {
+        let key = |k: &str| match self.new_values.get(k) {
+            Some(serde_json::Value::String(s)) => Some(s.clone()),
+            Some(serde_json::Value::Null) | None => None,
+            Some(v) => Some(v.to_string()),
+        };
+        vec![key("date"), key("at_date"), key("provinsi")]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [run {}] {} {}: {}",
+            self.changed_at.format("%Y-%m-%d %H:%M:%S"),
+            self.run_id.as_deref().unwrap_or("-"),
+            self.table_name,
+            self.subject(),
+            self.changes().join(", ")
+        )
+    }
+}
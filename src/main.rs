@@ -1,8 +1,12 @@
+extern crate async_trait;
 extern crate chrono;
 extern crate clap;
+extern crate flate2;
 extern crate futures;
+extern crate rand;
 extern crate reqwest;
 extern crate serde;
+extern crate sha2;
 extern crate sqlx;
 extern crate tokio;
 #[macro_use]
@@ -11,87 +15,269 @@ extern crate serde_json;
 #[macro_use]
 extern crate failure;
 
+mod archive;
+mod config;
+mod dashboard;
+mod drift;
+mod dryrun;
 mod endpoints;
+mod export;
 mod helpers;
+mod history;
+mod migrations;
 mod models;
+mod output;
+mod pgstore;
+mod province;
 mod queries;
+mod replay;
+mod retry;
+mod run;
+mod sqlite;
 mod store;
 
-use crate::store::PgStore;
+use crate::config::Config;
+use crate::history::Revision;
+use crate::replay::Replay;
+use crate::store::Store;
 use clap::{App, Arg};
 use endpoints::Request;
 use failure::Error;
 use std::env;
 use std::fs::{create_dir_all, read_to_string};
+use std::path::Path;
 use std::sync::Arc;
 
-const DEFAULT_JSON_DIR: &str = "/tmp/inacovid/json_out/";
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    #[serde(rename = "postgresDsn")]
-    postgres_dsn: Option<String>,
-    #[serde(rename = "jsonOutputDir")]
-    json_dir: Option<String>,
-}
-
-impl Config {
-    pub fn create_json_dir(&self) -> Result<(), std::io::Error> {
-        match &self.json_dir {
-            Some(path) => create_dir_all(path),
-            None => create_dir_all(DEFAULT_JSON_DIR),
-        }
-    }
-    pub fn set_db_dsn(&self) -> Result<(), Error> {
-        match &self.postgres_dsn {
-            Some(dsn) => {
-                std::env::set_var("DATABASE_URI", std::ffi::OsStr::new(dsn));
-                Ok(())
-            }
-            None => Err(format_err!(
-                "postgres dsn is not set or its value is not valid"
-            )),
-        }
-    }
+enum Command {
+    Ingest {
+        // one replay per archived reporting day, oldest first
+        replay: Option<Vec<Replay>>,
+        auto_migrate: bool,
+        atomic: bool,
+        dry_run: bool,
+    },
+    Migrate,
+    Export {
+        dir: String,
+    },
+    Dashboard {
+        out: String,
+    },
+    History {
+        date: Option<String>,
+        province: Option<String>,
+    },
 }
 
-fn init() -> Result<Config, Error> {
+fn init() -> Result<(Config, Command), Error> {
     let matches = App::new("inacovid")
         .version("0.1")
         .author("Alexander Adhyatma <alex@asiatech.dev>")
         .about("Gets Indonesian Covid19 data from gov't source, save it to postgres and json dir")
-        .args(&[Arg::with_name("config")
-            .help("Config file containing database dsn and json output dir")
-            .long("config")
-            .short('c')
-            .takes_value(true)
-            .required(true)])
+        .args(&[
+            Arg::with_name("config")
+                .help("Config file containing database dsn and json output dir")
+                .long("config")
+                .short('c')
+                .takes_value(true)
+                .required(true),
+            Arg::with_name("replay")
+                .help("Ingest archived responses from a directory or manifest.jsonl instead of the network")
+                .long("replay")
+                .short('r')
+                .takes_value(true),
+            Arg::with_name("auto-migrate")
+                .help("Applies pending database migrations before ingesting")
+                .long("auto-migrate"),
+            Arg::with_name("atomic")
+                .help("Commits all datasets of the run in one transaction, or none if any fails")
+                .long("atomic"),
+            Arg::with_name("dry-run")
+                .help("Fetches and validates the data, then shows how it differs from the database without writing anything")
+                .long("dry-run")
+                .conflicts_with("auto-migrate"),
+        ])
+        .subcommand(App::new("migrate").about("Applies pending database migrations"))
+        .subcommand(
+            App::new("export")
+                .about("Exports the daily and province history as Parquet, partitioned by month")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Output directory, defaults to the parquet dir of the config")
+                        .long("dir")
+                        .short('o')
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("dashboard")
+                .about("Renders a static HTML dashboard from the stored data")
+                .arg(
+                    Arg::with_name("out")
+                        .help("Output file, defaults to dashboard/index.html in the json output dir")
+                        .long("out")
+                        .short('o')
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("history")
+                .about("Shows how upstream figures were revised over time")
+                .args(&[
+                    Arg::with_name("date")
+                        .help("Reporting date, YYYY-MM-DD")
+                        .long("date")
+                        .short('d')
+                        .takes_value(true),
+                    Arg::with_name("province")
+                        .help("Province name or BPS code")
+                        .long("province")
+                        .short('p')
+                        .takes_value(true),
+                ]),
+        )
         .get_matches();
 
     let c = matches.value_of("config").unwrap(); // config file is required anyway
     let path = read_to_string(c)?;
     let config_file: Config = serde_json::from_str(&path)?;
-    config_file.create_json_dir()?;
+    // dry runs leave the json output dir alone
+    if !matches.is_present("dry-run") {
+        config_file.create_json_dir()?;
+    }
     config_file.set_db_dsn()?;
-    Ok(config_file)
+    if matches.subcommand_matches("migrate").is_some() {
+        return Ok((config_file, Command::Migrate));
+    }
+    if let Some(export) = matches.subcommand_matches("export") {
+        let dir = match export.value_of("dir") {
+            Some(d) if d.ends_with('/') => d.to_string(),
+            Some(d) => format!("{}/", d),
+            None => config_file.parquet_dir(),
+        };
+        return Ok((config_file, Command::Export { dir: dir }));
+    }
+    if let Some(dashboard) = matches.subcommand_matches("dashboard") {
+        let out = match dashboard.value_of("out") {
+            Some(o) => o.to_string(),
+            None => config_file.dashboard_path(),
+        };
+        return Ok((config_file, Command::Dashboard { out: out }));
+    }
+    if let Some(history) = matches.subcommand_matches("history") {
+        return Ok((
+            config_file,
+            Command::History {
+                date: history.value_of("date").map(|d| d.to_string()),
+                province: history.value_of("province").map(|p| p.to_string()),
+            },
+        ));
+    }
+    let replay = match matches.value_of("replay") {
+        Some(path) => Some(Replay::open(path, config_file.publish_cutoff)?),
+        None => None,
+    };
+    let atomic = matches.is_present("atomic") || config_file.atomic_run;
+    Ok((
+        config_file,
+        Command::Ingest {
+            replay: replay,
+            auto_migrate: matches.is_present("auto-migrate"),
+            atomic: atomic,
+            dry_run: matches.is_present("dry-run"),
+        },
+    ))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let config = init()?;
+    let (config, command) = init()?;
     let uri = env::var("DATABASE_URL")?;
-    let pool = sqlx::PgPool::new(uri.as_str())
-        .await
-        .map(|p| Arc::new(p))
-        .expect("Could not connect to postgres");
-    let store = PgStore::new(Arc::clone(&pool));
-    let new_request = Request::new(
-        store,
-        config.json_dir.unwrap_or(DEFAULT_JSON_DIR.to_string()),
+    let store: Arc<dyn Store> = Arc::from(
+        store::open(uri.as_str())
+            .await
+            .expect("Could not connect to the database"),
     );
-    let daily = new_request.fetch_daily().await?;
-    let cumulative = new_request.cumulative_stats(0).await?;
-    let per_province = new_request.fetch_province().await?;
-    println!("{}\n{}\n{}", daily, cumulative, per_province);
+    let (replay, atomic, dry_run) = match command {
+        Command::Ingest {
+            replay,
+            auto_migrate,
+            atomic,
+            dry_run,
+        } => {
+            if auto_migrate {
+                store.migrate().await?;
+                province::seed(store.as_ref()).await?;
+            }
+            store.verify_schema().await?;
+            (replay, atomic, dry_run)
+        }
+        Command::Migrate => {
+            let applied = store.migrate().await?;
+            println!(
+                "Applied {} migration(s), schema is at version {}",
+                applied.len(),
+                store.schema_version().await?
+            );
+            println!("Seeded {} provinces", province::seed(store.as_ref()).await?);
+            return Ok(());
+        }
+        Command::Export { dir } => {
+            store.verify_schema().await?;
+            println!(
+                "{}",
+                export::parquet(store.as_ref(), dir.as_str())
+                    .await?
+                    .join("\n")
+            );
+            return Ok(());
+        }
+        Command::Dashboard { out } => {
+            store.verify_schema().await?;
+            if let Some(parent) = Path::new(out.as_str()).parent() {
+                create_dir_all(parent)?;
+            }
+            println!("{}", dashboard::render(store.as_ref(), out.as_str()).await?);
+            return Ok(());
+        }
+        Command::History { date, province } => {
+            for rev in
+                Revision::timeline(store.as_ref(), date.as_deref(), province.as_deref()).await?
+            {
+                println!("{}", rev);
+            }
+            return Ok(());
+        }
+    };
+    let replays = match replay {
+        Some(days) => days.into_iter().map(Some).collect(),
+        None => vec![None],
+    };
+    // a day missing from the archive doesn't stop the ones after it
+    let mut failed = vec![];
+    for replay in replays {
+        let day = replay.as_ref().and_then(|r| r.day());
+        let new_request = Request::new(store.clone(), &config, replay, dry_run)?;
+        let result = if dry_run {
+            println!("Dry run {}, nothing is written", new_request.run_id());
+            new_request.dry_run().await
+        } else {
+            println!("Ingestion run {}", new_request.run_id());
+            new_request.run(atomic).await
+        };
+        match result {
+            Ok(messages) => println!("{}", messages.join("\n")),
+            Err(e) => match day {
+                Some(day) => {
+                    eprintln!("Replay of {} failed: {}", day, e);
+                    failed.push(day.to_string());
+                }
+                None => return Err(e),
+            },
+        }
+    }
+    if !failed.is_empty() {
+        return Err(format_err!("Replay failed for {}", failed.join(", ")));
+    }
     Ok(())
 }
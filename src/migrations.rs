@@ -0,0 +1,86 @@
+// ------ Embedded schema migrations -------- //
+// Applied in order by `Store::migrate`, never edit one that has been released,
+// add a new one instead. The Postgres ones tolerate databases that were set up
+// by hand from the former schema.sql. Schema changes need a migration for both
+// backends.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../schema/migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "raw_archives",
+        sql: include_str!("../schema/migrations/0002_raw_archives.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "observed_schemas",
+        sql: include_str!("../schema/migrations/0003_observed_schemas.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "updated_at",
+        sql: include_str!("../schema/migrations/0004_updated_at.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "revision_history",
+        sql: include_str!("../schema/migrations/0005_revision_history.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "date_types",
+        sql: include_str!("../schema/migrations/0006_date_types.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "province",
+        sql: include_str!("../schema/migrations/0007_province.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "province_key",
+        sql: include_str!("../schema/migrations/0008_province_key.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "ingestion_runs",
+        sql: include_str!("../schema/migrations/0009_ingestion_runs.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "observed_schemas_run",
+        sql: include_str!("../schema/migrations/0010_observed_schemas_run.sql"),
+    },
+];
+
+// the SQLite schema starts out at what the Postgres migrations had built up to
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../schema/sqlite/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "observed_schemas_run",
+        sql: include_str!("../schema/sqlite/0002_observed_schemas_run.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "run_lock_pid_start",
+        sql: include_str!("../schema/sqlite/0003_run_lock_pid_start.sql"),
+    },
+];
+
+pub fn latest_version(migrations: &[Migration]) -> i64 {
+    migrations.last().map(|m| m.version).unwrap_or(0)
+}
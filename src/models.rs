@@ -1,7 +1,10 @@
-use crate::helpers::timestamp_to_date;
-use crate::store::PgStore;
+use crate::helpers::{jakarta_date, timestamp_to_datetime};
+use crate::output::Record;
+use crate::province::ProvinceIndex;
+use crate::store::StoreTx;
+use chrono::{DateTime, NaiveDate, Utc};
 use failure::Error;
-use sqlx;
+use serde_json::{json, Map, Value};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InaCovid {
@@ -17,6 +20,8 @@ pub struct InaCovid {
     spatial_reference: Option<SpatialReference>,
     fields: Option<Vec<Field>>,
     features: Option<Vec<Feature>>,
+    #[serde(rename = "exceededTransferLimit")]
+    exceeded_transfer_limit: Option<bool>,
 }
 
 impl InaCovid {
@@ -27,11 +32,98 @@ impl InaCovid {
             Some(x) => x,
         }
     }
+    // `None` when the response carries no field metadata at all
+    pub fn get_fields(&self) -> Option<&[Field]> {
+        self.fields.as_ref().map(|x| x.as_slice())
+    }
+    pub fn feature_count(&self) -> usize {
+        match &self.features {
+            None => 0,
+            Some(x) => x.len(),
+        }
+    }
+    // ArcGIS sets this flag when there are more records past the current page
+    pub fn exceeded_transfer_limit(&self) -> bool {
+        self.exceeded_transfer_limit.unwrap_or(false)
+    }
+    // appends the features of the next page, keeping the metadata of the first one
+    pub fn merge(&mut self, page: InaCovid) {
+        let mut features = self.features.take().unwrap_or_default();
+        features.extend(page.features.unwrap_or_default());
+        self.features = Some(features);
+        self.exceeded_transfer_limit = page.exceeded_transfer_limit;
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Feature {
     attributes: Option<Attributes>,
+    // only returned when the query asks for `returnGeometry`
+    geometry: Option<Geometry>,
+}
+
+// esri json point or polygon, in the spatial reference requested with `outSR`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Geometry {
+    x: Option<f64>,
+    y: Option<f64>,
+    rings: Option<Vec<Vec<Vec<f64>>>>,
+}
+
+// esri outer rings run clockwise, holes counter-clockwise
+fn is_clockwise(ring: &[Vec<f64>]) -> bool {
+    let area: f64 = ring
+        .windows(2)
+        .filter(|w| w[0].len() >= 2 && w[1].len() >= 2)
+        .map(|w| (w[1][0] - w[0][0]) * (w[1][1] + w[0][1]))
+        .sum();
+    area > 0.0
+}
+
+// ray casting, a point on an edge may go either way
+fn ring_contains(ring: &[Vec<f64>], point: &[f64]) -> bool {
+    if point.len() < 2 {
+        return false;
+    }
+    let (x, y) = (point[0], point[1]);
+    let crossings = ring
+        .windows(2)
+        .filter(|w| w[0].len() >= 2 && w[1].len() >= 2)
+        .filter(|w| {
+            let (x0, y0, x1, y1) = (w[0][0], w[0][1], w[1][0], w[1][1]);
+            (y0 > y) != (y1 > y) && x < x0 + (y - y0) * (x1 - x0) / (y1 - y0)
+        })
+        .count();
+    crossings % 2 == 1
+}
+
+impl Geometry {
+    // GeoJSON wants the opposite winding, so every ring is reversed. Rings
+    // come in no particular order, each hole goes with the outer ring holding
+    // it, a hole outside of all of them is taken as an outer ring itself
+    pub fn to_geojson(&self) -> Option<Value> {
+        if let (Some(x), Some(y)) = (self.x, self.y) {
+            return Some(json!({"type": "Point", "coordinates": [x, y]}));
+        }
+        let reversed = |ring: &Vec<Vec<f64>>| ring.iter().rev().cloned().collect::<Vec<Vec<f64>>>();
+        let (outer, holes): (Vec<_>, Vec<_>) =
+            self.rings.as_ref()?.iter().partition(|r| is_clockwise(r));
+        let mut polygons = outer.iter().map(|r| vec![reversed(r)]).collect::<Vec<_>>();
+        for hole in holes {
+            let holder = hole
+                .first()
+                .and_then(|p| outer.iter().position(|o| ring_contains(o, p)));
+            match holder {
+                Some(i) => polygons[i].push(reversed(hole)),
+                None => polygons.push(vec![reversed(hole)]),
+            }
+        }
+        match polygons.len() {
+            0 => None,
+            1 => Some(json!({"type": "Polygon", "coordinates": polygons[0]})),
+            _ => Some(json!({"type": "MultiPolygon", "coordinates": polygons})),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -82,6 +174,34 @@ pub struct Attributes {
     pub pembaruan_terakhir: Option<i64>,
 }
 
+// the columns of the tabular outputs, in the order the records hold them
+pub const DAILY_COLUMNS: &[&str] = &[
+    "date",
+    "day",
+    "new_cases_per_day",
+    "cumulative_cases",
+    "under_treatment",
+    "under_treatment_per_day",
+    "under_treatment_percentage",
+    "recovered",
+    "recovered_per_day",
+    "recovered_percentage",
+    "deaths",
+    "deaths_per_day",
+    "deaths_percentage",
+    "under_investigation",
+    "latest_update",
+];
+pub const PROVINCE_COLUMNS: &[&str] = &[
+    "date",
+    "province_id",
+    "provinsi",
+    "positif",
+    "sembuh",
+    "meninggal",
+];
+pub const STATS_COLUMNS: &[&str] = &["at_date", "total_cases", "deaths", "recovered", "pdp"];
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DataProvinsiOptVec {
     provinsi_vec: Vec<Option<IndoCovidStats>>,
@@ -98,49 +218,194 @@ impl DataProvinsiOptVec {
             provinsi_vec: indo_covid_vec,
         }
     }
-    pub async fn insert_db_daily(&self, store: &PgStore) -> Result<(), Error> {
-        let mut tx = store.get_tx().await?;
-        for p in self.provinsi_vec.iter() {
-            match p {
-                Some(prov) => {
-                    sqlx::query!(
-            r#"
-                INSERT INTO covid_daily(day, date, new_cases_per_day, cumulative_cases, 
-                    under_treatment, under_treatment_per_day, under_treatment_percentage, recovered, recovered_per_day, 
-                    recovered_percentage, deaths, deaths_per_day, deaths_percentage, latest_update)
-                VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
-                ON CONFLICT ON CONSTRAINT covid_daily_date_key DO UPDATE SET existed = true
-            "#,
-            prov.day, prov.date, prov.new_cases_per_day, prov.cumulative_cases, prov.under_treatment, prov.under_treatment_per_day, prov.under_treatment_percentage, prov.recovered, prov.recovered_per_day,
-            prov.recovered_percentage, prov.deaths, prov.deaths_per_day, prov.deaths_percentage, prov.latest_update,
-        ).execute(&mut tx).await?;
+    pub fn rows(&self) -> Vec<&IndoCovidStats> {
+        self.provinsi_vec.iter().flatten().collect()
+    }
+    // flat rows for the tabular outputs, columns in a fixed order
+    pub fn daily_records(&self) -> Vec<Record> {
+        self.rows()
+            .into_iter()
+            .map(|prov| {
+                vec![
+                    ("date", json!(prov.date)),
+                    ("day", json!(prov.day)),
+                    ("new_cases_per_day", json!(prov.new_cases_per_day)),
+                    ("cumulative_cases", json!(prov.cumulative_cases)),
+                    ("under_treatment", json!(prov.under_treatment)),
+                    (
+                        "under_treatment_per_day",
+                        json!(prov.under_treatment_per_day),
+                    ),
+                    (
+                        "under_treatment_percentage",
+                        json!(prov.under_treatment_percentage),
+                    ),
+                    ("recovered", json!(prov.recovered)),
+                    ("recovered_per_day", json!(prov.recovered_per_day)),
+                    ("recovered_percentage", json!(prov.recovered_percentage)),
+                    ("deaths", json!(prov.deaths)),
+                    ("deaths_per_day", json!(prov.deaths_per_day)),
+                    ("deaths_percentage", json!(prov.deaths_percentage)),
+                    ("under_investigation", json!(prov.under_investigation)),
+                    ("latest_update", json!(prov.latest_update)),
+                ]
+            })
+            .collect()
+    }
+    // GeoJSON features with the figures of each province as properties, the
+    // geometry is null when upstream didn't return one
+    pub fn province_features(&self, ids: &[Option<i64>]) -> Vec<Value> {
+        self.rows()
+            .into_iter()
+            .zip(self.province_records(ids))
+            .map(|(prov, record)| {
+                json!({
+                    "type": "Feature",
+                    "geometry": prov.geometry.as_ref().and_then(|g| g.to_geojson()),
+                    "properties": record
+                        .into_iter()
+                        .map(|(c, v)| (c.to_string(), v))
+                        .collect::<Map<String, Value>>(),
+                })
+            })
+            .collect()
+    }
+    // `ids` are the codes the rows were stored under, as `insert_db_province` returns them
+    pub fn province_records(&self, ids: &[Option<i64>]) -> Vec<Record> {
+        self.rows()
+            .into_iter()
+            .zip(ids)
+            .map(|(prov, id)| {
+                vec![
+                    ("date", json!(prov.date)),
+                    ("province_id", json!(id)),
+                    ("provinsi", json!(prov.provinsi)),
+                    ("positif", json!(prov.positif)),
+                    ("sembuh", json!(prov.sembuh)),
+                    ("meninggal", json!(prov.meninggal)),
+                ]
+            })
+            .collect()
+    }
+    // pairs every row with the province code it is stored under, `None` for
+    // rows that can't be stored at all, counting those and the flagged ones
+    pub fn resolve_provinces(
+        &self,
+        provinces: &ProvinceIndex,
+        counts: &mut UpsertCounts,
+    ) -> Vec<(&IndoCovidStats, Option<i64>, bool)> {
+        let mut rows = vec![];
+        for prov in self.rows() {
+            let resolved = provinces.resolve(prov.province_id, prov.provinsi.as_deref());
+            let unknown = resolved.is_none();
+            // rows are keyed on (province_id, date), without a code there is nothing to key on
+            let province_id = match resolved.or(prov.province_id) {
+                Some(id) => id,
+                None => {
+                    eprintln!(
+                        "Skipping province {:?} on {:?}: no province code",
+                        prov.provinsi, prov.date
+                    );
+                    counts.skipped += 1;
+                    rows.push((prov, None, false));
+                    continue;
                 }
-                None => continue,
+            };
+            if unknown {
+                eprintln!(
+                    "Unknown province {:?} (code {}), stored as flagged",
+                    prov.provinsi, province_id
+                );
+                counts.flagged += 1;
             }
+            rows.push((prov, Some(province_id), unknown));
         }
-        tx.commit().await?;
-        Ok(())
+        rows
     }
-    pub async fn insert_db_province(&self, store: &PgStore) -> Result<(), Error> {
-        let mut tx = store.get_tx().await?;
-        for p in self.provinsi_vec.iter() {
-            match p {
-                Some(prov) => {
-                    let date_only = prov.date.as_ref().unwrap();
-                    let prov_date =
-                        format!("{}_{}", prov.provinsi.as_ref().unwrap(), &date_only[..9],);
-                    sqlx::query!(r#"
-                        INSERT INTO covid_province(province_id, date, provinsi, positif, sembuh, meninggal, prov_and_date)
-                        VALUES($1, $2, $3, $4, $5, $6, $7)
-                        ON CONFLICT ON CONSTRAINT covid_province_prov_and_date_key DO UPDATE SET existed = true
-                    "#,
-                    prov.province_id, prov.date, prov.provinsi, prov.positif, prov.sembuh, prov.meninggal, prov_date)
-                    .execute(&mut tx).await?;
-                }
-                None => continue,
-            }
+    // batches of at least `bulk_threshold` rows go through the store's bulk path, 0 disables it
+    pub async fn insert_db_daily(
+        &self,
+        tx: &mut dyn StoreTx,
+        bulk_threshold: usize,
+    ) -> Result<UpsertCounts, Error> {
+        let rows = self.rows();
+        if bulk_threshold > 0 && rows.len() >= bulk_threshold {
+            return tx.upsert_daily_bulk(&rows).await;
+        }
+        let mut counts = UpsertCounts::default();
+        for prov in rows {
+            counts.record(tx.upsert_daily(prov).await?);
+        }
+        Ok(counts)
+    }
+    // also returns the code each row was stored under, for the output files
+    pub async fn insert_db_province(
+        &self,
+        tx: &mut dyn StoreTx,
+        bulk_threshold: usize,
+    ) -> Result<(UpsertCounts, Vec<Option<i64>>), Error> {
+        let mut counts = UpsertCounts::default();
+        let provinces = ProvinceIndex::load(tx).await?;
+        let resolved = self.resolve_provinces(&provinces, &mut counts);
+        let ids = resolved.iter().map(|(_, id, _)| *id).collect();
+        let rows = resolved
+            .into_iter()
+            .filter_map(|(prov, id, unknown)| id.map(|id| (prov, id, unknown)))
+            .collect::<Vec<(&IndoCovidStats, i64, bool)>>();
+        if bulk_threshold > 0 && rows.len() >= bulk_threshold {
+            counts.add(tx.upsert_province_bulk(&rows).await?);
+            return Ok((counts, ids));
+        }
+        for (prov, province_id, unknown) in rows {
+            counts.record(tx.upsert_province(prov, province_id, unknown).await?);
+        }
+        Ok((counts, ids))
+    }
+}
+
+// outcome of upserting a dataset, rows whose values did not change are left untouched
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct UpsertCounts {
+    pub inserted: u64,
+    pub updated: u64,
+    pub unchanged: u64,
+    // rows that failed validation but were stored anyway
+    pub flagged: u64,
+    // rows that could not be stored at all
+    pub skipped: u64,
+}
+
+impl UpsertCounts {
+    // as returned by the `StoreTx` upserts
+    pub fn record(&mut self, inserted: Option<bool>) {
+        match inserted {
+            None => self.unchanged += 1,
+            Some(true) => self.inserted += 1,
+            Some(false) => self.updated += 1,
+        }
+    }
+    pub fn add(&mut self, other: UpsertCounts) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.flagged += other.flagged;
+        self.skipped += other.skipped;
+    }
+}
+
+impl std::fmt::Display for UpsertCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} inserted, {} updated, {} unchanged",
+            self.inserted, self.updated, self.unchanged
+        )?;
+        if self.flagged > 0 {
+            write!(f, ", {} flagged", self.flagged)?;
+        }
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
         }
-        tx.commit().await?;
         Ok(())
     }
 }
@@ -151,11 +416,11 @@ pub struct DataProvinsiOpt {
 }
 
 impl DataProvinsiOpt {
-    pub fn new(feat: Feature) -> Self {
+    pub fn new(feat: Feature, day: NaiveDate) -> Self {
+        let mut stats = IndoCovidStats::new(feat.attributes.expect("Attributes are nil"), day);
+        stats.geometry = feat.geometry;
         DataProvinsiOpt {
-            indo_covid_stats: Some(IndoCovidStats::new(
-                feat.attributes.expect("Attributes are nil"),
-            )),
+            indo_covid_stats: Some(stats),
         }
     }
 }
@@ -170,7 +435,7 @@ pub struct IndoCovidStats {
     pub day: Option<i64>,
     #[serde(rename = "Date")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub date: Option<String>,
+    pub date: Option<NaiveDate>,
     #[serde(rename = "NewCasesPerDay")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub new_cases_per_day: Option<i64>,
@@ -209,25 +474,32 @@ pub struct IndoCovidStats {
     pub under_treatment_per_day: Option<i64>,
     #[serde(rename = "Latestupdate")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub latest_update: Option<String>,
+    pub latest_update: Option<DateTime<Utc>>,
     #[serde(rename = "Provinsi")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    provinsi: Option<String>,
+    pub provinsi: Option<String>,
     #[serde(rename = "Positif")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    positif: Option<i64>,
+    pub positif: Option<i64>,
     #[serde(rename = "Sembuh")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    sembuh: Option<i64>,
+    pub sembuh: Option<i64>,
     #[serde(rename = "Meninggal")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    meninggal: Option<i64>,
+    pub meninggal: Option<i64>,
+    // kept out of the JSON output, it only goes to the GeoJSON one
+    #[serde(skip)]
+    pub geometry: Option<Geometry>,
 }
 
 impl IndoCovidStats {
-    pub fn new(attr: Attributes) -> Self {
-        let date = timestamp_to_date(attr.tanggal);
-        let latest_update = timestamp_to_date(attr.pembaruan_terakhir);
+    // `day` is the reporting day of the fetch, used for data that carries no date
+    pub fn new(attr: Attributes, day: NaiveDate) -> Self {
+        let date = match attr.tanggal {
+            Some(_) => jakarta_date(timestamp_to_datetime(attr.tanggal)),
+            None => day,
+        };
+        let latest_update = timestamp_to_datetime(attr.pembaruan_terakhir);
         IndoCovidStats {
             province_id: attr.kode_provi,
             provinsi: attr.provinsi,
@@ -249,11 +521,12 @@ impl IndoCovidStats {
             deaths_per_day: attr.jumlah_kasus_meninggal_per_hari,
             deaths_percentage: attr.persentase_pasien_meninggal,
             latest_update: Some(latest_update),
+            geometry: None,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Field {
     name: Option<String>,
     #[serde(rename = "type")]
@@ -267,6 +540,15 @@ pub struct Field {
     length: Option<i64>,
 }
 
+impl Field {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("")
+    }
+    pub fn field_type(&self) -> &str {
+        self.field_type.as_deref().unwrap_or("")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SpatialReference {
     wkid: Option<i64>,
@@ -292,7 +574,7 @@ pub struct CovidStatistics {
     #[serde(rename = "Pdp")]
     pub pdp: Option<i64>,
     #[serde(rename = "Date")]
-    pub created: Option<String>,
+    pub created: Option<NaiveDate>,
 }
 
 impl CovidStatistics {
@@ -301,7 +583,7 @@ impl CovidStatistics {
         deaths: Option<i64>,
         recovered: Option<i64>,
         pdp: Option<i64>,
-        date: Option<String>,
+        date: Option<NaiveDate>,
     ) -> Self {
         CovidStatistics {
             total_cases: total_cases,
@@ -311,23 +593,90 @@ impl CovidStatistics {
             created: date,
         }
     }
-    pub async fn insert_db(&self, store: &PgStore) -> Result<(), Error> {
-        let mut tx = store.get_tx().await?;
-        sqlx::query!(
-            r#"
-                INSERT INTO covid_stats (deaths, total_cases, recovered, pdp, at_date)
-                VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT ON CONSTRAINT covid_stats_at_date_key DO UPDATE SET existed = true
-            "#,
-            self.deaths,
-            self.total_cases,
-            self.recovered,
-            self.pdp,
-            self.created
-        )
-        .execute(&mut tx)
-        .await?;
-        tx.commit().await?;
-        Ok(())
+    pub fn records(&self) -> Vec<Record> {
+        vec![vec![
+            ("at_date", json!(self.created)),
+            ("total_cases", json!(self.total_cases)),
+            ("deaths", json!(self.deaths)),
+            ("recovered", json!(self.recovered)),
+            ("pdp", json!(self.pdp)),
+        ]]
+    }
+    pub async fn insert_db(&self, tx: &mut dyn StoreTx) -> Result<UpsertCounts, Error> {
+        let mut counts = UpsertCounts::default();
+        counts.record(tx.upsert_stats(self).await?);
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(rings: Value) -> Geometry {
+        serde_json::from_value(json!({ "rings": rings })).unwrap()
+    }
+
+    // clockwise as esri has outer rings, and its hole counter-clockwise
+    fn outer(x: f64) -> Value {
+        json!([
+            [x, 0.0],
+            [x, 10.0],
+            [x + 10.0, 10.0],
+            [x + 10.0, 0.0],
+            [x, 0.0]
+        ])
+    }
+
+    fn hole(x: f64) -> Value {
+        json!([
+            [x + 2.0, 2.0],
+            [x + 4.0, 2.0],
+            [x + 4.0, 4.0],
+            [x + 2.0, 4.0],
+            [x + 2.0, 2.0]
+        ])
+    }
+
+    fn geojson(ring: Value) -> Value {
+        let mut ring = ring.as_array().unwrap().clone();
+        ring.reverse();
+        Value::Array(ring)
+    }
+
+    #[test]
+    fn polygon_keeps_its_hole() {
+        assert_eq!(
+            polygon(json!([outer(0.0), hole(0.0)])).to_geojson(),
+            Some(json!({
+                "type": "Polygon",
+                "coordinates": [geojson(outer(0.0)), geojson(hole(0.0))],
+            }))
+        );
+    }
+
+    #[test]
+    fn hole_before_its_outer_ring() {
+        assert_eq!(
+            polygon(json!([hole(0.0), outer(0.0)])).to_geojson(),
+            Some(json!({
+                "type": "Polygon",
+                "coordinates": [geojson(outer(0.0)), geojson(hole(0.0))],
+            }))
+        );
+    }
+
+    #[test]
+    fn multipolygon_holes_go_with_their_outer_ring() {
+        assert_eq!(
+            polygon(json!([outer(0.0), hole(20.0), outer(20.0)])).to_geojson(),
+            Some(json!({
+                "type": "MultiPolygon",
+                "coordinates": [
+                    [geojson(outer(0.0))],
+                    [geojson(outer(20.0)), geojson(hole(20.0))],
+                ],
+            }))
+        );
     }
 }
@@ -0,0 +1,269 @@
+use crate::run::Dataset;
+use chrono::NaiveDate;
+use failure::Error;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::{read, rename, File};
+use std::io::Write;
+
+// bumped whenever the layout of the JSON output files changes, version 1 being
+// the legacy string encoded files
+pub const FORMAT_VERSION: u32 = 2;
+
+// ------ JSON output config -------- //
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub pretty: bool,
+    // writes the data as one JSON string literal without the envelope, like
+    // releases before format version 2 did
+    #[serde(rename = "legacyStringEncoding")]
+    pub legacy_string_encoding: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            pretty: true,
+            legacy_string_encoding: false,
+        }
+    }
+}
+
+// `rename` replaces the target in one step, so readers polling the directory
+// never see a partly written file
+pub fn write_atomic(path: &str, body: &[u8]) -> Result<(), Error> {
+    let tmp = format!("{}.tmp-{}", path, std::process::id());
+    let mut file = File::create(tmp.as_str())?;
+    file.write_all(body)?;
+    file.sync_all()?;
+    rename(tmp.as_str(), path)?;
+    Ok(())
+}
+
+// ------ Output formats -------- //
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Csv,
+    Ndjson,
+    // province map, requests the province geometry upstream
+    GeoJson,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+            Format::GeoJson => "geojson",
+        }
+    }
+}
+
+// formats written for each dataset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputFormats {
+    pub daily: Vec<Format>,
+    pub cumulative: Vec<Format>,
+    pub province: Vec<Format>,
+}
+
+impl Default for OutputFormats {
+    fn default() -> Self {
+        OutputFormats {
+            daily: vec![Format::Json],
+            cumulative: vec![Format::Json],
+            province: vec![Format::Json],
+        }
+    }
+}
+
+impl OutputFormats {
+    pub fn of(&self, dataset: Dataset) -> &[Format] {
+        match dataset {
+            Dataset::Daily => &self.daily,
+            Dataset::Cumulative => &self.cumulative,
+            Dataset::Province => &self.province,
+        }
+    }
+
+    pub fn province_geometry(&self) -> bool {
+        self.province.contains(&Format::GeoJson)
+    }
+}
+
+// one row of the tabular formats, every row of a dataset has the same columns
+pub type Record = Vec<(&'static str, Value)>;
+
+fn csv_field(v: &Value) -> String {
+    let field = match v {
+        Value::Null => return String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+// header row then one line per record, missing values are left empty; the
+// header is written for datasets without records too
+fn csv(columns: &[&str], records: &[Record]) -> String {
+    let mut out = String::new();
+    out.push_str(columns.join(",").as_str());
+    out.push('\n');
+    for record in records {
+        let fields = columns
+            .iter()
+            .map(|c| match record.iter().find(|(k, _)| k == c) {
+                Some((_, v)) => csv_field(v),
+                None => String::new(),
+            })
+            .collect::<Vec<String>>();
+        out.push_str(fields.join(",").as_str());
+        out.push('\n');
+    }
+    out
+}
+
+// one json object per line, keys in column order
+fn ndjson(records: &[Record]) -> Result<String, Error> {
+    let mut out = String::new();
+    for record in records {
+        let mut fields = vec![];
+        for (c, v) in record {
+            fields.push(format!("{}:{}", serde_json::to_string(c)?, v));
+        }
+        out.push_str(format!("{{{}}}\n", fields.join(",")).as_str());
+    }
+    Ok(out)
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    #[serde(rename = "formatVersion")]
+    format_version: u32,
+    dataset: &'a str,
+    date: NaiveDate,
+    data: &'a T,
+}
+
+// writes `<dataset>-<YYYY-MM-DD>.<ext>` files into the json output dir, and
+// `<dataset>-latest.<ext>` copies of the newest ones
+pub struct OutputWriter {
+    dir: String,
+    config: OutputConfig,
+    formats: OutputFormats,
+}
+
+impl OutputWriter {
+    pub fn new(
+        jsondir: &str,
+        config: &OutputConfig,
+        formats: &OutputFormats,
+    ) -> Result<Self, Error> {
+        // only the province data has geometry
+        for dataset in [Dataset::Daily, Dataset::Cumulative].iter() {
+            if formats.of(*dataset).contains(&Format::GeoJson) {
+                return Err(format_err!(
+                    "GeoJSON output is only available for the province data, not {}",
+                    dataset.name()
+                ));
+            }
+        }
+        Ok(OutputWriter {
+            dir: jsondir.to_string(),
+            config: config.clone(),
+            formats: formats.clone(),
+        })
+    }
+
+    pub fn path(&self, dataset: Dataset, day: NaiveDate, format: Format) -> String {
+        format!(
+            "{}{}-{}.{}",
+            self.dir,
+            dataset.name(),
+            day.format("%Y-%m-%d"),
+            format.extension()
+        )
+    }
+
+    pub fn latest_path(&self, dataset: Dataset, format: Format) -> String {
+        format!(
+            "{}{}-latest.{}",
+            self.dir,
+            dataset.name(),
+            format.extension()
+        )
+    }
+
+    fn json<T: Serialize>(
+        &self,
+        dataset: Dataset,
+        day: NaiveDate,
+        data: &T,
+    ) -> Result<String, Error> {
+        if self.config.legacy_string_encoding {
+            return Ok(serde_json::to_string(&serde_json::to_string_pretty(data)?)?);
+        }
+        let envelope = Envelope {
+            format_version: FORMAT_VERSION,
+            dataset: dataset.name(),
+            date: day,
+            data: data,
+        };
+        if self.config.pretty {
+            Ok(serde_json::to_string_pretty(&envelope)?)
+        } else {
+            Ok(serde_json::to_string(&envelope)?)
+        }
+    }
+
+    fn geojson(&self, features: &[Value]) -> Result<String, Error> {
+        let collection = json!({"type": "FeatureCollection", "features": features});
+        if self.config.pretty {
+            Ok(serde_json::to_string_pretty(&collection)?)
+        } else {
+            Ok(serde_json::to_string(&collection)?)
+        }
+    }
+
+    // `data` is what the JSON output holds, `records` the same rows flattened
+    // into `columns` for the tabular formats and `features` their GeoJSON
+    // features, if any
+    pub fn write<T: Serialize>(
+        &self,
+        dataset: Dataset,
+        day: NaiveDate,
+        data: &T,
+        columns: &[&str],
+        records: &[Record],
+        features: &[Value],
+    ) -> Result<(), Error> {
+        for format in self.formats.of(dataset) {
+            let body = match format {
+                Format::Json => self.json(dataset, day, data)?,
+                Format::Csv => csv(columns, records),
+                Format::Ndjson => ndjson(records)?,
+                Format::GeoJson => self.geojson(features)?,
+            };
+            write_atomic(self.path(dataset, day, *format).as_str(), body.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // points the `latest` aliases at the files written for `day`, once the run succeeded
+    pub fn publish_latest(&self, dataset: Dataset, day: NaiveDate) -> Result<(), Error> {
+        for format in self.formats.of(dataset) {
+            let body = read(self.path(dataset, day, *format))?;
+            write_atomic(self.latest_path(dataset, *format).as_str(), &body)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,758 @@
+use crate::archive::ArchiveEntry;
+use crate::drift::ObservedSchema;
+use crate::history::Revision;
+use crate::migrations::{Migration, MIGRATIONS};
+use crate::models::{CovidStatistics, IndoCovidStats, UpsertCounts};
+use crate::province::Province;
+use crate::store::{RunLock, Store, StoreTx};
+use async_trait::async_trait;
+use failure::Error;
+use serde_json::Value;
+use sqlx::{
+    pool::PoolConnection, postgres::PgPool, Connect, Connection, Error as SqlxError, Executor,
+    PgConnection, Row, Transaction,
+};
+use std::sync::Arc;
+
+// "inacovid" in ASCII, held via pg_advisory_xact_lock so two processes never migrate at once
+const MIGRATION_LOCK_KEY: i64 = 0x696e_6163_6f76_6964;
+// "inac_run", held for the whole ingestion run on a dedicated connection
+const RUN_LOCK_KEY: i64 = 0x696e_6163_5f72_756e;
+
+pub type PgTx = Transaction<PoolConnection<PgConnection>>;
+
+// rows per multi-row INSERT into a staging table, keeps the bind parameters well under 65535
+const STAGING_CHUNK: usize = 1000;
+
+// bulk upserts stage rows in a temp table and merge it with one statement, `seq`
+// keeps the last of duplicate keys like the row by row path would
+const DAILY_STAGING: &str = r#"
+    CREATE TEMP TABLE IF NOT EXISTS covid_daily_staging ON COMMIT DROP AS
+    SELECT 0::bigint AS seq, day, date, new_cases_per_day, cumulative_cases, under_treatment,
+        under_treatment_per_day, under_treatment_percentage, recovered, recovered_per_day,
+        recovered_percentage, deaths, deaths_per_day, deaths_percentage, latest_update
+    FROM covid_daily WITH NO DATA
+"#;
+
+const DAILY_MERGE: &str = r#"
+    INSERT INTO covid_daily(day, date, new_cases_per_day, cumulative_cases,
+        under_treatment, under_treatment_per_day, under_treatment_percentage, recovered, recovered_per_day,
+        recovered_percentage, deaths, deaths_per_day, deaths_percentage, latest_update)
+    SELECT DISTINCT ON (date) day, date, new_cases_per_day, cumulative_cases,
+        under_treatment, under_treatment_per_day, under_treatment_percentage, recovered, recovered_per_day,
+        recovered_percentage, deaths, deaths_per_day, deaths_percentage, latest_update
+    FROM covid_daily_staging ORDER BY date, seq DESC
+    ON CONFLICT ON CONSTRAINT covid_daily_date_key DO UPDATE SET
+        day = EXCLUDED.day, new_cases_per_day = EXCLUDED.new_cases_per_day, cumulative_cases = EXCLUDED.cumulative_cases,
+        under_treatment = EXCLUDED.under_treatment, under_treatment_per_day = EXCLUDED.under_treatment_per_day,
+        under_treatment_percentage = EXCLUDED.under_treatment_percentage, recovered = EXCLUDED.recovered,
+        recovered_per_day = EXCLUDED.recovered_per_day, recovered_percentage = EXCLUDED.recovered_percentage,
+        deaths = EXCLUDED.deaths, deaths_per_day = EXCLUDED.deaths_per_day, deaths_percentage = EXCLUDED.deaths_percentage,
+        latest_update = EXCLUDED.latest_update, existed = true, updated_at = NOW()
+    WHERE (covid_daily.day, covid_daily.new_cases_per_day, covid_daily.cumulative_cases, covid_daily.under_treatment,
+        covid_daily.under_treatment_per_day, covid_daily.under_treatment_percentage, covid_daily.recovered,
+        covid_daily.recovered_per_day, covid_daily.recovered_percentage, covid_daily.deaths, covid_daily.deaths_per_day,
+        covid_daily.deaths_percentage)
+    IS DISTINCT FROM (EXCLUDED.day, EXCLUDED.new_cases_per_day, EXCLUDED.cumulative_cases, EXCLUDED.under_treatment,
+        EXCLUDED.under_treatment_per_day, EXCLUDED.under_treatment_percentage, EXCLUDED.recovered,
+        EXCLUDED.recovered_per_day, EXCLUDED.recovered_percentage, EXCLUDED.deaths, EXCLUDED.deaths_per_day,
+        EXCLUDED.deaths_percentage)
+    RETURNING (xmax = 0) AS inserted
+"#;
+
+const PROVINCE_STAGING: &str = r#"
+    CREATE TEMP TABLE IF NOT EXISTS covid_province_staging ON COMMIT DROP AS
+    SELECT 0::bigint AS seq, province_id, date, provinsi, positif, sembuh, meninggal, unknown_province
+    FROM covid_province WITH NO DATA
+"#;
+
+const PROVINCE_MERGE: &str = r#"
+    INSERT INTO covid_province(province_id, date, provinsi, positif, sembuh, meninggal, unknown_province)
+    SELECT DISTINCT ON (province_id, date) province_id, date, provinsi, positif, sembuh, meninggal, unknown_province
+    FROM covid_province_staging ORDER BY province_id, date, seq DESC
+    ON CONFLICT ON CONSTRAINT covid_province_province_id_date_key DO UPDATE SET
+        provinsi = EXCLUDED.provinsi, positif = EXCLUDED.positif, sembuh = EXCLUDED.sembuh,
+        meninggal = EXCLUDED.meninggal, unknown_province = EXCLUDED.unknown_province,
+        existed = true, updated_at = NOW()
+    WHERE (covid_province.provinsi, covid_province.positif, covid_province.sembuh, covid_province.meninggal,
+        covid_province.unknown_province)
+    IS DISTINCT FROM (EXCLUDED.provinsi, EXCLUDED.positif, EXCLUDED.sembuh, EXCLUDED.meninggal,
+        EXCLUDED.unknown_province)
+    RETURNING (xmax = 0) AS inserted
+"#;
+
+// `($1, $2), ($3, $4)` for 2 rows of 2 columns
+fn placeholders(rows: usize, columns: usize) -> String {
+    (0..rows)
+        .map(|r| {
+            let row = (1..=columns)
+                .map(|c| format!("${}", r * columns + c))
+                .collect::<Vec<String>>();
+            format!("({})", row.join(", "))
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// `RETURNING (xmax = 0) AS inserted` is true for fresh rows, no row means the
+// conflict clause filtered it out, i.e. nothing changed
+fn upserted<T: Into<Option<bool>>>(inserted: Option<T>) -> Option<bool> {
+    inserted.map(|i| i.into().unwrap_or(false))
+}
+
+// ------ Postgres store -------- //
+pub struct PgStore {
+    pool: Arc<PgPool>,
+    // the run lock gets a connection of its own, outside the pool
+    uri: String,
+}
+
+impl PgStore {
+    pub fn new(uri: &str, pool: Arc<sqlx::PgPool>) -> Self {
+        PgStore {
+            pool: pool,
+            uri: uri.to_string(),
+        }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub async fn get_tx(&self) -> Result<PgTx, SqlxError> {
+        self.pool.begin().await
+    }
+}
+
+#[async_trait]
+impl Store for PgStore {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    async fn schema_version(&self) -> Result<i64, Error> {
+        let present: bool =
+            sqlx::query("SELECT to_regclass('schema_migrations') IS NOT NULL AS present")
+                .fetch_one(self.pool())
+                .await?
+                .get("present");
+        if !present {
+            return Ok(0);
+        }
+        let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_migrations")
+            .fetch_one(self.pool())
+            .await?;
+        Ok(row.get("version"))
+    }
+
+    async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        let mut tx = self.get_tx().await?;
+        tx.execute(
+            r#"
+                CREATE TABLE IF NOT EXISTS schema_migrations(
+                    version BIGINT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )
+            "#,
+        )
+        .await?;
+        tx.commit().await?;
+
+        let mut applied = vec![];
+        for m in MIGRATIONS.iter() {
+            let mut tx = self.get_tx().await?;
+            sqlx::query("SELECT pg_advisory_xact_lock($1)")
+                .bind(MIGRATION_LOCK_KEY)
+                .execute(&mut tx)
+                .await?;
+            let done = sqlx::query("SELECT version FROM schema_migrations WHERE version = $1")
+                .bind(m.version)
+                .fetch_optional(&mut tx)
+                .await?
+                .is_some();
+            if done {
+                continue;
+            }
+            if let Err(e) = tx.execute(m.sql).await {
+                return Err(format_err!(
+                    "Migration {} ({}) failed: {}",
+                    m.version,
+                    m.name,
+                    e
+                ));
+            }
+            sqlx::query("INSERT INTO schema_migrations (version, name) VALUES ($1, $2)")
+                .bind(m.version)
+                .bind(m.name)
+                .execute(&mut tx)
+                .await?;
+            tx.commit().await?;
+            applied.push(m.version);
+        }
+        Ok(applied)
+    }
+
+    async fn seed_provinces(&self, provinces: &[Province]) -> Result<(), Error> {
+        let mut tx = self.get_tx().await?;
+        for p in provinces.iter() {
+            sqlx::query(
+                r#"
+                    INSERT INTO province (code, name_id, name_en, iso_3166_2, island_group, population)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (code) DO UPDATE SET
+                        name_id = EXCLUDED.name_id, name_en = EXCLUDED.name_en, iso_3166_2 = EXCLUDED.iso_3166_2,
+                        island_group = EXCLUDED.island_group, population = EXCLUDED.population, updated_at = NOW()
+                "#,
+            )
+            .bind(p.code)
+            .bind(p.name_id.clone())
+            .bind(p.name_en.clone())
+            .bind(p.iso_3166_2.clone())
+            .bind(p.island_group.clone())
+            .bind(p.population)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // the run id is picked up by the revision history trigger
+    async fn begin_run(&self, run_id: &str) -> Result<Box<dyn StoreTx>, Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SELECT set_config('inacovid.run_id', $1, true)")
+            .bind(run_id)
+            .execute(&mut tx)
+            .await?;
+        Ok(Box::new(PgStoreTx { tx }))
+    }
+
+    // The session lock lives on a dedicated connection, never one of the pool:
+    // closing or dropping it ends the session, which releases the lock even when
+    // the run fails or is cancelled.
+    async fn try_lock_run(&self, run_id: &str) -> Result<Option<Box<dyn RunLock>>, Error> {
+        let mut conn = PgConnection::connect(self.uri.as_str()).await?;
+        // shows up in pg_stat_activity for whoever finds the lock taken
+        sqlx::query("SELECT set_config('application_name', $1, false)")
+            .bind(format!("inacovid {}", run_id))
+            .execute(&mut conn)
+            .await?;
+        let locked: bool = sqlx::query("SELECT pg_try_advisory_lock($1) AS locked")
+            .bind(RUN_LOCK_KEY)
+            .fetch_one(&mut conn)
+            .await?
+            .get("locked");
+        if locked {
+            return Ok(Some(Box::new(PgRunLock { conn })));
+        }
+        conn.close().await?;
+        Ok(None)
+    }
+
+    // bigint advisory keys are split into classid (high) and objid (low) in pg_locks
+    async fn run_lock_holder(&self) -> Result<String, Error> {
+        let row = sqlx::query(
+            r#"
+                SELECT format('pid %s (%s) from %s, connected since %s', a.pid,
+                    COALESCE(NULLIF(a.application_name, ''), 'unknown'),
+                    COALESCE(host(a.client_addr), 'local socket'),
+                    date_trunc('second', a.backend_start)) AS holder
+                FROM pg_locks l JOIN pg_stat_activity a ON a.pid = l.pid
+                WHERE l.locktype = 'advisory' AND l.granted AND l.objsubid = 1
+                    AND ((l.classid::bigint << 32) | l.objid::bigint) = $1
+            "#,
+        )
+        .bind(RUN_LOCK_KEY)
+        .fetch_optional(self.pool())
+        .await?;
+        // the holder may have finished in between
+        Ok(row
+            .map(|r| r.get("holder"))
+            .unwrap_or_else(|| "holder already gone".to_string()))
+    }
+
+    async fn start_run(&self, run_id: &str, atomic: bool) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO ingestion_runs (run_id, status, atomic) VALUES ($1, 'running', $2)",
+        )
+        .bind(run_id)
+        .bind(atomic)
+        .execute(self.pool())
+        .await?;
+        Ok(())
+    }
+
+    async fn finish_run(
+        &self,
+        run_id: &str,
+        status: &str,
+        counts: &str,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+                UPDATE ingestion_runs SET finished_at = NOW(), status = $2, counts = $3::jsonb, error = $4
+                WHERE run_id = $1
+            "#,
+        )
+        .bind(run_id)
+        .bind(status)
+        .bind(counts)
+        .bind(error)
+        .execute(self.pool())
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_archive(&self, entry: &ArchiveEntry) -> Result<(), Error> {
+        let mut tx = self.get_tx().await?;
+        sqlx::query(
+            r#"
+                INSERT INTO raw_archives (dataset, url, fetched_at, sha256, path, compressed, bytes)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(entry.dataset.clone())
+        .bind(entry.url.clone())
+        .bind(entry.fetched_at)
+        .bind(entry.sha256.clone())
+        .bind(entry.path.clone())
+        .bind(entry.compressed)
+        .bind(entry.bytes)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_observed_schema(&self, schema: &ObservedSchema) -> Result<(), Error> {
+        let mut tx = self.get_tx().await?;
+        sqlx::query(
+            r#"
+                INSERT INTO observed_schemas (run_id, dataset, observed_at, fields, fingerprint, drift)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(schema.run_id.clone())
+        .bind(schema.dataset.clone())
+        .bind(schema.observed_at)
+        .bind(schema.fields.clone())
+        .bind(schema.fingerprint.clone())
+        .bind(schema.drift.clone())
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn revisions(
+        &self,
+        date: Option<&str>,
+        province: Option<&str>,
+    ) -> Result<Vec<Revision>, Error> {
+        let rows = sqlx::query(
+            r#"
+                SELECT table_name, changed_at, run_id, old_values::text AS old_values, new_values::text AS new_values
+                FROM revision_history
+                WHERE ($1::text IS NULL OR COALESCE(new_values->>'date', new_values->>'at_date') LIKE $1 || '%')
+                AND ($2::text IS NULL OR new_values->>'provinsi' ILIKE $2 OR new_values->>'province_id' = $2)
+                ORDER BY changed_at, id
+            "#,
+        )
+        .bind(date.map(|d| d.to_string()))
+        .bind(province.map(|p| p.to_string()))
+        .fetch_all(self.pool())
+        .await?;
+        let mut revisions = vec![];
+        for row in rows.iter() {
+            let old_values: String = row.get("old_values");
+            let new_values: String = row.get("new_values");
+            revisions.push(Revision::new(
+                row.get("table_name"),
+                row.get("changed_at"),
+                row.get("run_id"),
+                serde_json::from_str(old_values.as_str())?,
+                serde_json::from_str(new_values.as_str())?,
+            ));
+        }
+        Ok(revisions)
+    }
+
+    async fn table_rows(
+        &self,
+        table: &str,
+        columns: &[&str],
+        order_by: &str,
+    ) -> Result<Vec<Value>, Error> {
+        let select = format!(
+            "SELECT json_build_object({})::text AS exported FROM {} ORDER BY {}",
+            columns
+                .iter()
+                .map(|c| format!("'{0}', {0}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table,
+            order_by
+        );
+        let rows = sqlx::query(select.as_str()).fetch_all(self.pool()).await?;
+        let mut values = vec![];
+        for row in rows.iter() {
+            let exported: String = row.get("exported");
+            values.push(serde_json::from_str(exported.as_str())?);
+        }
+        Ok(values)
+    }
+}
+
+pub struct PgStoreTx {
+    tx: PgTx,
+}
+
+#[async_trait]
+impl StoreTx for PgStoreTx {
+    async fn provinces(&mut self) -> Result<Vec<(i64, String, String)>, Error> {
+        let rows = sqlx::query("SELECT code, name_id, name_en FROM province")
+            .fetch_all(&mut self.tx)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("code"), row.get("name_id"), row.get("name_en")))
+            .collect())
+    }
+
+    async fn upsert_daily(&mut self, prov: &IndoCovidStats) -> Result<Option<bool>, Error> {
+        let row = sqlx::query(
+            r#"
+                INSERT INTO covid_daily(day, date, new_cases_per_day, cumulative_cases,
+                    under_treatment, under_treatment_per_day, under_treatment_percentage, recovered, recovered_per_day,
+                    recovered_percentage, deaths, deaths_per_day, deaths_percentage, latest_update)
+                VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                ON CONFLICT ON CONSTRAINT covid_daily_date_key DO UPDATE SET
+                    day = EXCLUDED.day, new_cases_per_day = EXCLUDED.new_cases_per_day, cumulative_cases = EXCLUDED.cumulative_cases,
+                    under_treatment = EXCLUDED.under_treatment, under_treatment_per_day = EXCLUDED.under_treatment_per_day,
+                    under_treatment_percentage = EXCLUDED.under_treatment_percentage, recovered = EXCLUDED.recovered,
+                    recovered_per_day = EXCLUDED.recovered_per_day, recovered_percentage = EXCLUDED.recovered_percentage,
+                    deaths = EXCLUDED.deaths, deaths_per_day = EXCLUDED.deaths_per_day, deaths_percentage = EXCLUDED.deaths_percentage,
+                    latest_update = EXCLUDED.latest_update, existed = true, updated_at = NOW()
+                WHERE (covid_daily.day, covid_daily.new_cases_per_day, covid_daily.cumulative_cases, covid_daily.under_treatment,
+                    covid_daily.under_treatment_per_day, covid_daily.under_treatment_percentage, covid_daily.recovered,
+                    covid_daily.recovered_per_day, covid_daily.recovered_percentage, covid_daily.deaths, covid_daily.deaths_per_day,
+                    covid_daily.deaths_percentage)
+                IS DISTINCT FROM (EXCLUDED.day, EXCLUDED.new_cases_per_day, EXCLUDED.cumulative_cases, EXCLUDED.under_treatment,
+                    EXCLUDED.under_treatment_per_day, EXCLUDED.under_treatment_percentage, EXCLUDED.recovered,
+                    EXCLUDED.recovered_per_day, EXCLUDED.recovered_percentage, EXCLUDED.deaths, EXCLUDED.deaths_per_day,
+                    EXCLUDED.deaths_percentage)
+                RETURNING (xmax = 0) AS inserted
+            "#,
+        )
+        .bind(prov.day)
+        .bind(prov.date)
+        .bind(prov.new_cases_per_day)
+        .bind(prov.cumulative_cases)
+        .bind(prov.under_treatment)
+        .bind(prov.under_treatment_per_day)
+        .bind(prov.under_treatment_percentage)
+        .bind(prov.recovered)
+        .bind(prov.recovered_per_day)
+        .bind(prov.recovered_percentage)
+        .bind(prov.deaths)
+        .bind(prov.deaths_per_day)
+        .bind(prov.deaths_percentage)
+        .bind(prov.latest_update)
+        .fetch_optional(&mut self.tx)
+        .await?;
+        Ok(upserted(row.map(|r| r.get::<bool, _>("inserted"))))
+    }
+
+    async fn upsert_province(
+        &mut self,
+        prov: &IndoCovidStats,
+        province_id: i64,
+        unknown: bool,
+    ) -> Result<Option<bool>, Error> {
+        let row = sqlx::query(
+            r#"
+                INSERT INTO covid_province(province_id, date, provinsi, positif, sembuh, meninggal, unknown_province)
+                VALUES($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT ON CONSTRAINT covid_province_province_id_date_key DO UPDATE SET
+                    provinsi = EXCLUDED.provinsi, positif = EXCLUDED.positif, sembuh = EXCLUDED.sembuh,
+                    meninggal = EXCLUDED.meninggal, unknown_province = EXCLUDED.unknown_province,
+                    existed = true, updated_at = NOW()
+                WHERE (covid_province.provinsi, covid_province.positif, covid_province.sembuh, covid_province.meninggal,
+                    covid_province.unknown_province)
+                IS DISTINCT FROM (EXCLUDED.provinsi, EXCLUDED.positif, EXCLUDED.sembuh, EXCLUDED.meninggal,
+                    EXCLUDED.unknown_province)
+                RETURNING (xmax = 0) AS inserted
+            "#,
+        )
+        .bind(province_id)
+        .bind(prov.date)
+        .bind(prov.provinsi.clone())
+        .bind(prov.positif)
+        .bind(prov.sembuh)
+        .bind(prov.meninggal)
+        .bind(unknown)
+        .fetch_optional(&mut self.tx)
+        .await?;
+        Ok(upserted(row.map(|r| r.get::<bool, _>("inserted"))))
+    }
+
+    async fn upsert_stats(&mut self, stats: &CovidStatistics) -> Result<Option<bool>, Error> {
+        let row = sqlx::query(
+            r#"
+                INSERT INTO covid_stats (deaths, total_cases, recovered, pdp, at_date)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT ON CONSTRAINT covid_stats_at_date_key DO UPDATE SET
+                    deaths = EXCLUDED.deaths, total_cases = EXCLUDED.total_cases,
+                    recovered = EXCLUDED.recovered, pdp = EXCLUDED.pdp,
+                    existed = true, updated_at = NOW()
+                WHERE (covid_stats.deaths, covid_stats.total_cases, covid_stats.recovered, covid_stats.pdp)
+                IS DISTINCT FROM (EXCLUDED.deaths, EXCLUDED.total_cases, EXCLUDED.recovered, EXCLUDED.pdp)
+                RETURNING (xmax = 0) AS inserted
+            "#,
+        )
+        .bind(stats.deaths)
+        .bind(stats.total_cases)
+        .bind(stats.recovered)
+        .bind(stats.pdp)
+        .bind(stats.created)
+        .fetch_optional(&mut self.tx)
+        .await?;
+        Ok(upserted(row.map(|r| r.get::<bool, _>("inserted"))))
+    }
+
+    async fn upsert_daily_bulk(&mut self, rows: &[&IndoCovidStats]) -> Result<UpsertCounts, Error> {
+        sqlx::query(DAILY_STAGING).execute(&mut self.tx).await?;
+        sqlx::query("TRUNCATE covid_daily_staging")
+            .execute(&mut self.tx)
+            .await?;
+        for (n, chunk) in rows.chunks(STAGING_CHUNK).enumerate() {
+            let sql = format!(
+                "INSERT INTO covid_daily_staging (seq, day, date, new_cases_per_day, cumulative_cases, \
+                 under_treatment, under_treatment_per_day, under_treatment_percentage, recovered, \
+                 recovered_per_day, recovered_percentage, deaths, deaths_per_day, deaths_percentage, \
+                 latest_update) VALUES {}",
+                placeholders(chunk.len(), 15)
+            );
+            let mut query = sqlx::query(sql.as_str());
+            for (i, prov) in chunk.iter().enumerate() {
+                query = query
+                    .bind((n * STAGING_CHUNK + i) as i64)
+                    .bind(prov.day)
+                    .bind(prov.date)
+                    .bind(prov.new_cases_per_day)
+                    .bind(prov.cumulative_cases)
+                    .bind(prov.under_treatment)
+                    .bind(prov.under_treatment_per_day)
+                    .bind(prov.under_treatment_percentage)
+                    .bind(prov.recovered)
+                    .bind(prov.recovered_per_day)
+                    .bind(prov.recovered_percentage)
+                    .bind(prov.deaths)
+                    .bind(prov.deaths_per_day)
+                    .bind(prov.deaths_percentage)
+                    .bind(prov.latest_update);
+            }
+            query.execute(&mut self.tx).await?;
+        }
+        let merged = sqlx::query(DAILY_MERGE)
+            .fetch_all(&mut self.tx)
+            .await?
+            .iter()
+            .map(|row| row.get("inserted"))
+            .collect::<Vec<bool>>();
+        Ok(merge_counts(rows.len(), &merged))
+    }
+
+    async fn upsert_province_bulk(
+        &mut self,
+        rows: &[(&IndoCovidStats, i64, bool)],
+    ) -> Result<UpsertCounts, Error> {
+        sqlx::query(PROVINCE_STAGING).execute(&mut self.tx).await?;
+        sqlx::query("TRUNCATE covid_province_staging")
+            .execute(&mut self.tx)
+            .await?;
+        for (n, chunk) in rows.chunks(STAGING_CHUNK).enumerate() {
+            let sql = format!(
+                "INSERT INTO covid_province_staging (seq, province_id, date, provinsi, positif, sembuh, \
+                 meninggal, unknown_province) VALUES {}",
+                placeholders(chunk.len(), 8)
+            );
+            let mut query = sqlx::query(sql.as_str());
+            for (i, (prov, province_id, unknown)) in chunk.iter().enumerate() {
+                query = query
+                    .bind((n * STAGING_CHUNK + i) as i64)
+                    .bind(*province_id)
+                    .bind(prov.date)
+                    .bind(prov.provinsi.clone())
+                    .bind(prov.positif)
+                    .bind(prov.sembuh)
+                    .bind(prov.meninggal)
+                    .bind(*unknown);
+            }
+            query.execute(&mut self.tx).await?;
+        }
+        let merged = sqlx::query(PROVINCE_MERGE)
+            .fetch_all(&mut self.tx)
+            .await?
+            .iter()
+            .map(|row| row.get("inserted"))
+            .collect::<Vec<bool>>();
+        Ok(merge_counts(rows.len(), &merged))
+    }
+
+    async fn stored_values(
+        &mut self,
+        table: &str,
+        key: &[(&str, Value)],
+        columns: &[&str],
+    ) -> Result<Option<Value>, Error> {
+        // keys are compared as text, so dates and codes bind the same way
+        let select = format!(
+            "SELECT json_build_object({})::text AS stored FROM {} WHERE {}",
+            columns
+                .iter()
+                .map(|c| format!("'{0}', {0}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table,
+            key.iter()
+                .enumerate()
+                .map(|(i, (c, _))| format!("{}::text = ${}", c, i + 1))
+                .collect::<Vec<String>>()
+                .join(" AND ")
+        );
+        let mut query = sqlx::query(select.as_str());
+        for (_, v) in key {
+            query = query.bind(match v {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            });
+        }
+        let stored: Option<String> = query
+            .fetch_optional(&mut self.tx)
+            .await?
+            .map(|row| row.get("stored"));
+        match stored {
+            Some(s) => Ok(Some(serde_json::from_str(s.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.tx.commit().await?;
+        Ok(())
+    }
+}
+
+// rows left out of the merge's RETURNING were unchanged, or duplicates of a staged key
+fn merge_counts(staged: usize, merged: &[bool]) -> UpsertCounts {
+    let mut counts = UpsertCounts::default();
+    for inserted in merged {
+        counts.record(Some(*inserted));
+    }
+    counts.unchanged = (staged - merged.len()) as u64;
+    counts
+}
+
+// session level advisory lock, released explicitly or when its connection closes
+pub struct PgRunLock {
+    conn: PgConnection,
+}
+
+#[async_trait]
+impl RunLock for PgRunLock {
+    // the connection is closed, and the lock with it, even if the unlock fails
+    async fn release(self: Box<Self>) -> Result<(), Error> {
+        let mut conn = self.conn;
+        let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(RUN_LOCK_KEY)
+            .execute(&mut conn)
+            .await;
+        conn.close().await?;
+        unlocked?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // migrations that need a server run against INACOVID_TEST_DATABASE_URL, in a
+    // schema of their own that is dropped afterwards
+    async fn test_connection() -> Result<Option<(PgConnection, String)>, Error> {
+        let url = match env::var("INACOVID_TEST_DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("INACOVID_TEST_DATABASE_URL is not set, skipping");
+                return Ok(None);
+            }
+        };
+        let mut conn = PgConnection::connect(url.as_str()).await?;
+        let schema = format!("inacovid_test_{}", std::process::id());
+        conn.execute(format!("CREATE SCHEMA {0}; SET search_path TO {0}", schema).as_str())
+            .await?;
+        Ok(Some((conn, schema)))
+    }
+
+    // applies the migrations after `from` up to and including `to`
+    async fn migrate_between(conn: &mut PgConnection, from: i64, to: i64) -> Result<(), Error> {
+        for m in MIGRATIONS
+            .iter()
+            .filter(|m| m.version > from && m.version <= to)
+        {
+            conn.execute(m.sql).await?;
+        }
+        Ok(())
+    }
+
+    async fn province_key_dedup(conn: &mut PgConnection) -> Result<Vec<(i64, String)>, Error> {
+        migrate_between(conn, 0, 7).await?;
+        // two spellings of a province could claim the same day under the old key
+        conn.execute(
+            r#"
+                INSERT INTO covid_province (province_id, date, provinsi, prov_and_date, updated_at) VALUES
+                    (31, '2020-05-09', 'never updated', 'a', NULL),
+                    (31, '2020-05-09', 'newest', 'b', '2020-05-10 10:00+00'),
+                    (31, '2020-05-09', 'older', 'c', '2020-05-09 10:00+00'),
+                    (32, '2020-05-10', 'first', 'd', NULL),
+                    (32, '2020-05-10', 'last written', 'e', NULL),
+                    (33, '2020-05-10', 'single', 'f', NULL)
+            "#,
+        )
+        .await?;
+        migrate_between(conn, 7, 8).await?;
+        let rows = sqlx::query(
+            "SELECT province_id, provinsi FROM covid_province ORDER BY province_id, date",
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(rows
+            .iter()
+            .map(|r| (r.get("province_id"), r.get("provinsi")))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn province_key_migration_keeps_newest_row() -> Result<(), Error> {
+        let (mut conn, schema) = match test_connection().await? {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        let rows = province_key_dedup(&mut conn).await;
+        conn.execute(format!("DROP SCHEMA {} CASCADE", schema).as_str())
+            .await?;
+        assert_eq!(
+            rows?,
+            vec![
+                (31, "newest".to_string()),
+                (32, "last written".to_string()),
+                (33, "single".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::store::{Store, StoreTx};
+use failure::Error;
+use std::collections::HashMap;
+
+const BUNDLED_PROVINCES: &str = include_str!("../data/provinces.json");
+
+// ------ Province reference data -------- //
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Province {
+    pub code: i64,
+    #[serde(rename = "nameId")]
+    pub name_id: String,
+    #[serde(rename = "nameEn")]
+    pub name_en: String,
+    #[serde(rename = "iso3166_2")]
+    pub iso_3166_2: String,
+    #[serde(rename = "islandGroup")]
+    pub island_group: String,
+    pub population: Option<i64>,
+    // other spellings seen upstream, only used to resolve names
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+pub fn bundled() -> Result<Vec<Province>, Error> {
+    match serde_json::from_str(BUNDLED_PROVINCES) {
+        Ok(p) => Ok(p),
+        Err(e) => Err(format_err!("Bundled province data is invalid: {}", e)),
+    }
+}
+
+// upserts the bundled provinces into the province table
+pub async fn seed(store: &dyn Store) -> Result<usize, Error> {
+    let provinces = bundled()?;
+    store.seed_provinces(&provinces).await?;
+    Ok(provinces.len())
+}
+
+// "D.I. Yogyakarta" and "DI Yogyakarta" should resolve alike
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+pub struct ProvinceIndex {
+    by_code: HashMap<i64, String>,
+    by_name: HashMap<String, i64>,
+}
+
+impl ProvinceIndex {
+    // provinces from the reference table, plus the bundled aliases of those
+    pub async fn load(tx: &mut dyn StoreTx) -> Result<Self, Error> {
+        let rows = tx.provinces().await?;
+        if rows.is_empty() {
+            return Err(format_err!(
+                "The province table is empty: run `inacovid migrate` to seed it"
+            ));
+        }
+        let mut by_code = HashMap::new();
+        let mut by_name = HashMap::new();
+        for (code, name_id, name_en) in rows.into_iter() {
+            by_name.insert(normalize(name_id.as_str()), code);
+            by_name.insert(normalize(name_en.as_str()), code);
+            by_code.insert(code, name_id);
+        }
+        for p in bundled()?.iter().filter(|p| by_code.contains_key(&p.code)) {
+            for alias in p.aliases.iter() {
+                by_name.insert(normalize(alias.as_str()), p.code);
+            }
+        }
+        Ok(ProvinceIndex { by_code, by_name })
+    }
+
+    // BPS code of an upstream row, by its code or else by its name
+    pub fn resolve(&self, code: Option<i64>, name: Option<&str>) -> Option<i64> {
+        let by_name = name.and_then(|n| self.by_name.get(&normalize(n)).cloned());
+        match code {
+            Some(c) if self.by_code.contains_key(&c) => {
+                if let Some(other) = by_name.filter(|other| *other != c) {
+                    eprintln!(
+                        "Province {:?} has code {} upstream but is known as {}",
+                        name, c, other
+                    );
+                }
+                Some(c)
+            }
+            _ => by_name,
+        }
+    }
+}
@@ -1,14 +1,67 @@
 use failure::Error;
 use reqwest::header;
+use std::collections::HashMap;
+use std::time::Duration;
 use url::Url;
 
-pub fn build_headers() -> header::HeaderMap {
+const DEFAULT_USER_AGENT: &str = concat!(
+    "inacovid/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/FrankSantoso/inacovid)"
+);
+
+// ------ HTTP client config -------- //
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    #[serde(rename = "connectTimeoutSecs")]
+    pub connect_timeout_secs: Option<u64>,
+    // reqwest applies this to the whole request, from connecting until the body is read
+    #[serde(rename = "readTimeoutSecs")]
+    pub read_timeout_secs: Option<u64>,
+    pub proxy: Option<String>,
+    #[serde(rename = "userAgent")]
+    pub user_agent: String,
+    pub headers: HashMap<String, String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout_secs: Some(10),
+            read_timeout_secs: Some(60),
+            proxy: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+pub fn build_client(http: &HttpConfig) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::Client::builder().default_headers(build_headers(http)?);
+    if let Some(secs) = http.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = http.read_timeout_secs {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    if let Some(proxy) = &http.proxy {
+        match reqwest::Proxy::all(proxy.as_str()) {
+            Ok(p) => builder = builder.proxy(p),
+            Err(e) => return Err(format_err!("Proxy {} is invalid: {}", proxy, e)),
+        }
+    }
+    Ok(builder.build()?)
+}
+
+pub fn build_headers(http: &HttpConfig) -> Result<header::HeaderMap, Error> {
     let mut headers = header::HeaderMap::new();
     headers.insert(
         header::USER_AGENT,
-        "Mozilla/5.0 (X11; OpenSUSE; Linux x86_64; rv:74.0) Gecko/20100101 Firefox/74.0"
-            .parse()
-            .expect("Invalid UA"),
+        match header::HeaderValue::from_str(http.user_agent.as_str()) {
+            Ok(v) => v,
+            Err(_) => return Err(format_err!("Invalid UA {}", http.user_agent)),
+        },
     );
     headers.insert(
         "Accept",
@@ -18,6 +71,7 @@ pub fn build_headers() -> header::HeaderMap {
         "Accept-Language",
         "en-US,en;q=0.5".parse().expect("Invalid Accept Lang"),
     );
+    // some arcgis hosted services check where the request comes from
     headers.insert(
         "Origin",
         "https://inacovid19.maps.arcgis.com"
@@ -25,7 +79,19 @@ pub fn build_headers() -> header::HeaderMap {
             .expect("Invalid Origin"),
     );
     headers.insert("TE", "Trailers".parse().expect("Invalid TE"));
-    headers
+    // extra headers override the defaults above
+    for (k, v) in http.headers.iter() {
+        let name = match header::HeaderName::from_bytes(k.as_bytes()) {
+            Ok(n) => n,
+            Err(_) => return Err(format_err!("Invalid header name {}", k)),
+        };
+        let value = match header::HeaderValue::from_str(v.as_str()) {
+            Ok(v) => v,
+            Err(_) => return Err(format_err!("Invalid value for header {}", k)),
+        };
+        headers.insert(name, value);
+    }
+    Ok(headers)
 }
 
 pub fn make_request_url(uri: &str, qparam: &str) -> Result<String, Error> {
@@ -0,0 +1,204 @@
+use crate::archive::ArchiveEntry;
+use crate::helpers::reporting_day;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use failure::Error;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{read_dir, read_to_string, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// ------ Offline replay of archived responses -------- //
+// A replay source is either a directory holding `<dataset>.json[.gz]` files
+// (or archived `<dataset>-<timestamp>-<hash>.json[.gz]` ones), or a
+// manifest.jsonl written by the archiver. Archived responses are replayed one
+// reporting day at a time, as of the day they were fetched.
+
+// a response body on disk, checked against the manifest's SHA-256 if known
+struct Recorded {
+    path: PathBuf,
+    sha256: Option<String>,
+}
+
+pub struct Replay {
+    // `None` for hand placed `<dataset>.json` files, replayed as of the current day
+    day: Option<NaiveDate>,
+    sources: HashMap<String, Recorded>,
+}
+
+// the newest response of each dataset per reporting day
+#[derive(Default)]
+struct Days(BTreeMap<NaiveDate, HashMap<String, (DateTime<Utc>, Recorded)>>);
+
+impl Days {
+    fn add(&mut self, cutoff: NaiveTime, dataset: &str, fetched_at: DateTime<Utc>, rec: Recorded) {
+        let day = self
+            .0
+            .entry(reporting_day(fetched_at, cutoff))
+            .or_insert_with(HashMap::new);
+        let newer = match day.get(dataset) {
+            Some((cur, _)) => fetched_at > *cur,
+            None => true,
+        };
+        if newer {
+            day.insert(dataset.to_string(), (fetched_at, rec));
+        }
+    }
+
+    fn into_replays(self) -> Vec<Replay> {
+        self.0
+            .into_iter()
+            .map(|(day, sources)| Replay {
+                day: Some(day),
+                sources: sources
+                    .into_iter()
+                    .map(|(dataset, (_, rec))| (dataset, rec))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl Replay {
+    // one replay per archived reporting day, oldest first; `cutoff` is the
+    // publish cutoff the days are derived with
+    pub fn open(path: &str, cutoff: NaiveTime) -> Result<Vec<Self>, Error> {
+        let p = Path::new(path);
+        let replays = if p.is_dir() {
+            open_dir(p, cutoff)?
+        } else {
+            open_manifest(p, cutoff)?
+        };
+        if replays.is_empty() {
+            return Err(format_err!("Nothing to replay in {}", path));
+        }
+        Ok(replays)
+    }
+
+    pub fn day(&self) -> Option<NaiveDate> {
+        self.day
+    }
+
+    // returns the raw body recorded for a dataset, e.g. `daily-p0`
+    pub fn load(&self, dataset: &str) -> Result<String, Error> {
+        let rec = match self.sources.get(dataset) {
+            Some(r) => r,
+            None => {
+                return Err(format_err!(
+                    "No replay file for {}{}",
+                    dataset,
+                    match self.day {
+                        Some(day) => format!(" on {}", day),
+                        None => String::new(),
+                    }
+                ))
+            }
+        };
+        let body = read_body(&rec.path)?;
+        if let Some(expected) = &rec.sha256 {
+            let sha256 = format!("{:x}", Sha256::digest(body.as_bytes()));
+            if sha256 != *expected {
+                return Err(format_err!(
+                    "Checksum mismatch for {}: expected {}, got {}",
+                    rec.path.display(),
+                    expected,
+                    sha256
+                ));
+            }
+        }
+        Ok(body)
+    }
+}
+
+fn open_manifest(p: &Path, cutoff: NaiveTime) -> Result<Vec<Replay>, Error> {
+    let base = p.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let mut days = Days::default();
+    for (i, line) in read_to_string(p)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: ArchiveEntry = match serde_json::from_str(line) {
+            Ok(e) => e,
+            Err(e) => return Err(format_err!("Invalid manifest line {}: {}", i + 1, e)),
+        };
+        let mut file = PathBuf::from(entry.path.as_str());
+        // archives may have been moved along with their manifest
+        if !file.exists() {
+            if let Some(name) = file.file_name() {
+                file = base.join(name);
+            }
+        }
+        days.add(
+            cutoff,
+            entry.dataset.as_str(),
+            entry.fetched_at,
+            Recorded {
+                path: file,
+                sha256: Some(entry.sha256),
+            },
+        );
+    }
+    Ok(days.into_replays())
+}
+
+// hand placed `<dataset>.json[.gz]` files win over archived ones
+fn open_dir(dir: &Path, cutoff: NaiveTime) -> Result<Vec<Replay>, Error> {
+    let mut plain = HashMap::new();
+    let mut days = Days::default();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => continue,
+        };
+        let stem = match name
+            .strip_suffix(".json.gz")
+            .or_else(|| name.strip_suffix(".json"))
+        {
+            Some(s) => s.to_string(),
+            None => continue,
+        };
+        let rec = Recorded {
+            path: path,
+            sha256: None,
+        };
+        match archived_name(stem.as_str()) {
+            Some((dataset, fetched_at)) => days.add(cutoff, dataset, fetched_at, rec),
+            None => {
+                plain.insert(stem, rec);
+            }
+        }
+    }
+    if !plain.is_empty() {
+        return Ok(vec![Replay {
+            day: None,
+            sources: plain,
+        }]);
+    }
+    Ok(days.into_replays())
+}
+
+// splits `<dataset>-<timestamp>-<hash>` as written by the archiver
+fn archived_name(stem: &str) -> Option<(&str, DateTime<Utc>)> {
+    let mut parts = stem.rsplitn(3, '-');
+    let hash = parts.next()?;
+    let timestamp = parts.next()?;
+    let dataset = parts.next()?;
+    if hash.len() != 12 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let fetched_at = NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%S%.3fZ").ok()?;
+    Some((dataset, DateTime::from_utc(fetched_at, Utc)))
+}
+
+fn read_body(path: &Path) -> Result<String, Error> {
+    let mut body = String::new();
+    let mut file = File::open(path)?;
+    if path.extension().map(|e| e == "gz").unwrap_or(false) {
+        GzDecoder::new(file).read_to_string(&mut body)?;
+    } else {
+        file.read_to_string(&mut body)?;
+    }
+    Ok(body)
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use failure::Error;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::time::Duration;
+
+// ------ Retry policy for upstream fetches -------- //
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    #[serde(rename = "maxAttempts")]
+    pub max_attempts: u32,
+    #[serde(rename = "baseDelayMs")]
+    pub base_delay_ms: u64,
+    #[serde(rename = "maxDelayMs")]
+    pub max_delay_ms: u64,
+    #[serde(rename = "retryableStatus")]
+    pub retryable_status: Vec<u16>,
+    #[serde(rename = "respectRetryAfter")]
+    pub respect_retry_after: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            retryable_status: vec![408, 429, 500, 502, 503, 504],
+            respect_retry_after: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_retryable_status(&self, status: u16) -> bool {
+        self.retryable_status.contains(&status)
+    }
+
+    // exponential backoff with full jitter, unless the server told us how long to wait
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let max_delay = Duration::from_millis(self.max_delay_ms);
+        if let (true, Some(wait)) = (self.respect_retry_after, retry_after) {
+            return wait.min(max_delay);
+        }
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32))
+            .min(self.max_delay_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(0, exp + 1))
+    }
+}
+
+// outcome of a single failed attempt
+pub enum FetchError {
+    Transient(Error, Option<Duration>),
+    Fatal(Error),
+}
+
+pub fn classify_reqwest(e: reqwest::Error) -> FetchError {
+    if e.is_timeout() || e.is_request() || e.is_body() {
+        FetchError::Transient(e.into(), None)
+    } else {
+        FetchError::Fatal(e.into())
+    }
+}
+
+// Retry-After is either delta-seconds or an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
@@ -0,0 +1,78 @@
+use crate::models::UpsertCounts;
+use crate::store::Store;
+use failure::Error;
+use std::collections::BTreeMap;
+
+// ------ Ingestion runs -------- //
+// what to do when another process is already running an ingestion
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockPolicy {
+    Wait,
+    Skip,
+    Fail,
+}
+
+impl Default for LockPolicy {
+    fn default() -> Self {
+        LockPolicy::Wait
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dataset {
+    Daily,
+    Cumulative,
+    Province,
+}
+
+impl Dataset {
+    // in the order they are ingested
+    pub const ALL: [Dataset; 3] = [Dataset::Daily, Dataset::Cumulative, Dataset::Province];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dataset::Daily => "daily",
+            Dataset::Cumulative => "cumulative",
+            Dataset::Province => "province",
+        }
+    }
+}
+
+// bookkeeping of a run in ingestion_runs, written outside of the run's own
+// transactions so failed runs are recorded too
+pub struct RunLog {
+    run_id: String,
+    counts: BTreeMap<&'static str, UpsertCounts>,
+}
+
+impl RunLog {
+    pub async fn start(store: &dyn Store, run_id: &str, atomic: bool) -> Result<Self, Error> {
+        store.start_run(run_id, atomic).await?;
+        Ok(RunLog {
+            run_id: run_id.to_string(),
+            counts: BTreeMap::new(),
+        })
+    }
+
+    // only committed datasets are recorded
+    pub fn record(&mut self, dataset: Dataset, counts: UpsertCounts) {
+        self.counts.insert(dataset.name(), counts);
+    }
+
+    pub async fn finish(&self, store: &dyn Store, error: Option<&Error>) -> Result<(), Error> {
+        let status = if error.is_some() {
+            "failed"
+        } else {
+            "succeeded"
+        };
+        store
+            .finish_run(
+                self.run_id.as_str(),
+                status,
+                serde_json::to_string(&self.counts)?.as_str(),
+                error.map(|e| e.to_string()),
+            )
+            .await
+    }
+}
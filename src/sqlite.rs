@@ -0,0 +1,822 @@
+use crate::archive::ArchiveEntry;
+use crate::drift::ObservedSchema;
+use crate::history::Revision;
+use crate::migrations::{Migration, SQLITE_MIGRATIONS};
+use crate::models::{CovidStatistics, IndoCovidStats};
+use crate::province::Province;
+use crate::store::{same_value, RunLock, Store, StoreTx};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use failure::Error;
+use serde_json::{json, Value};
+use sqlx::{
+    pool::PoolConnection,
+    sqlite::{SqliteConnection, SqlitePool},
+    Row, Transaction,
+};
+use std::fs::read_to_string;
+use std::path::Path;
+
+type SqliteTx = Transaction<PoolConnection<SqliteConnection>>;
+
+// binds json values in order, sqlite columns are loosely typed anyway
+macro_rules! bind_values {
+    ($query:expr, $values:expr) => {{
+        let mut q = $query;
+        for v in $values {
+            q = match v {
+                Value::Null => q.bind(None::<String>),
+                Value::Bool(b) => q.bind(*b),
+                Value::Number(n) => match n.as_i64() {
+                    Some(i) => q.bind(i),
+                    None => q.bind(n.as_f64()),
+                },
+                Value::String(s) => q.bind(s.clone()),
+                other => q.bind(other.to_string()),
+            };
+        }
+        q
+    }};
+}
+
+fn now() -> String {
+    format_timestamp(Utc::now())
+}
+
+fn format_timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn format_date(d: Option<NaiveDate>) -> Value {
+    json!(d.map(|d| d.format("%Y-%m-%d").to_string()))
+}
+
+// sqlite prepares one statement at a time
+fn statements(sql: &str) -> Vec<&str> {
+    sql.split(';')
+        .map(|s| s.trim())
+        .filter(|s| {
+            s.lines().any(|l| {
+                let l = l.trim();
+                !l.is_empty() && !l.starts_with("--")
+            })
+        })
+        .collect()
+}
+
+// the start time of a process in clock ticks since boot, field 22 of
+// /proc/<pid>/stat
+fn process_start(pid: i64) -> Option<i64> {
+    let stat = read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name in field 2 may hold spaces and parentheses itself
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+// whether the process that took the run lock still runs, where that can't be
+// told it is assumed to. Our own pid is never the holder as a lock is only
+// asked for while not holding it, and a pid reused since has another start time
+fn holder_alive(pid: i64, started: Option<i64>) -> bool {
+    if pid == std::process::id() as i64 {
+        return false;
+    }
+    if !cfg!(target_os = "linux") {
+        return true;
+    }
+    if !Path::new(format!("/proc/{}", pid).as_str()).exists() {
+        return false;
+    }
+    match (started, process_start(pid)) {
+        (Some(started), Some(current)) => started == current,
+        _ => true,
+    }
+}
+
+// ------ Embedded SQLite store -------- //
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    // e.g. `sqlite://inacovid.db`
+    pub async fn open(uri: &str) -> Result<Self, Error> {
+        Ok(SqliteStore {
+            pool: SqlitePool::new(uri).await?,
+        })
+    }
+
+    async fn insert_run_lock(&self, run_id: &str) -> Result<bool, Error> {
+        let inserted = sqlx::query(
+            "INSERT OR IGNORE INTO run_lock (id, run_id, pid, pid_start, acquired_at) VALUES (1, ?, ?, ?, ?)",
+        )
+        .bind(run_id)
+        .bind(std::process::id() as i64)
+        .bind(process_start(std::process::id() as i64))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+        Ok(inserted > 0)
+    }
+
+    // unlike an advisory lock the row outlives a run that crashed or was killed,
+    // it is removed once its process is gone
+    async fn clear_stale_run_lock(&self) -> Result<bool, Error> {
+        let row = sqlx::query("SELECT run_id, pid, pid_start FROM run_lock WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        let (holder, pid, started): (String, i64, Option<i64>) = match row {
+            Some(r) => (r.get("run_id"), r.get("pid"), r.get("pid_start")),
+            None => return Ok(true),
+        };
+        if holder_alive(pid, started) {
+            return Ok(false);
+        }
+        eprintln!(
+            "Removing the run lock of inacovid {}, its process {} no longer runs",
+            holder, pid
+        );
+        sqlx::query("DELETE FROM run_lock WHERE run_id = ? AND pid = ?")
+            .bind(holder)
+            .bind(pid)
+            .execute(&self.pool)
+            .await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    fn migrations(&self) -> &'static [Migration] {
+        SQLITE_MIGRATIONS
+    }
+
+    async fn schema_version(&self) -> Result<i64, Error> {
+        let present: i64 = sqlx::query(
+            "SELECT COUNT(*) AS present FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .get("present");
+        if present == 0 {
+            return Ok(0);
+        }
+        let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_migrations")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get("version"))
+    }
+
+    async fn migrate(&self) -> Result<Vec<i64>, Error> {
+        sqlx::query(
+            r#"
+                CREATE TABLE IF NOT EXISTS schema_migrations(
+                    version INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at TEXT NOT NULL
+                )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let mut applied = vec![];
+        for m in SQLITE_MIGRATIONS.iter() {
+            let mut tx = self.pool.begin().await?;
+            let done = sqlx::query("SELECT version FROM schema_migrations WHERE version = ?")
+                .bind(m.version)
+                .fetch_optional(&mut tx)
+                .await?
+                .is_some();
+            if done {
+                continue;
+            }
+            for statement in statements(m.sql) {
+                if let Err(e) = sqlx::query(statement).execute(&mut tx).await {
+                    return Err(format_err!(
+                        "Migration {} ({}) failed: {}",
+                        m.version,
+                        m.name,
+                        e
+                    ));
+                }
+            }
+            sqlx::query(
+                "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?, ?, ?)",
+            )
+            .bind(m.version)
+            .bind(m.name)
+            .bind(now())
+            .execute(&mut tx)
+            .await?;
+            tx.commit().await?;
+            applied.push(m.version);
+        }
+        Ok(applied)
+    }
+
+    async fn seed_provinces(&self, provinces: &[Province]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        for p in provinces.iter() {
+            sqlx::query(
+                r#"
+                    INSERT INTO province (code, name_id, name_en, iso_3166_2, island_group, population, updated_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT (code) DO UPDATE SET
+                        name_id = excluded.name_id, name_en = excluded.name_en, iso_3166_2 = excluded.iso_3166_2,
+                        island_group = excluded.island_group, population = excluded.population, updated_at = excluded.updated_at
+                "#,
+            )
+            .bind(p.code)
+            .bind(p.name_id.clone())
+            .bind(p.name_en.clone())
+            .bind(p.iso_3166_2.clone())
+            .bind(p.island_group.clone())
+            .bind(p.population)
+            .bind(now())
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn begin_run(&self, run_id: &str) -> Result<Box<dyn StoreTx>, Error> {
+        Ok(Box::new(SqliteStoreTx {
+            tx: self.pool.begin().await?,
+            run_id: run_id.to_string(),
+        }))
+    }
+
+    async fn try_lock_run(&self, run_id: &str) -> Result<Option<Box<dyn RunLock>>, Error> {
+        if !self.insert_run_lock(run_id).await? {
+            // another process may have taken it in between
+            if !self.clear_stale_run_lock().await? || !self.insert_run_lock(run_id).await? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(Box::new(SqliteRunLock {
+            pool: self.pool.clone(),
+            run_id: run_id.to_string(),
+        })))
+    }
+
+    async fn run_lock_holder(&self) -> Result<String, Error> {
+        let row = sqlx::query("SELECT run_id, pid, acquired_at FROM run_lock WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(match row {
+            Some(r) => format!(
+                "pid {} (inacovid {}) since {}",
+                r.get::<i64, _>("pid"),
+                r.get::<String, _>("run_id"),
+                r.get::<String, _>("acquired_at")
+            ),
+            None => "holder already gone".to_string(),
+        })
+    }
+
+    async fn start_run(&self, run_id: &str, atomic: bool) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO ingestion_runs (run_id, started_at, status, atomic) VALUES (?, ?, 'running', ?)",
+        )
+        .bind(run_id)
+        .bind(now())
+        .bind(atomic)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn finish_run(
+        &self,
+        run_id: &str,
+        status: &str,
+        counts: &str,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "UPDATE ingestion_runs SET finished_at = ?, status = ?, counts = ?, error = ? WHERE run_id = ?",
+        )
+        .bind(now())
+        .bind(status)
+        .bind(counts)
+        .bind(error)
+        .bind(run_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_archive(&self, entry: &ArchiveEntry) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+                INSERT INTO raw_archives (dataset, url, fetched_at, sha256, path, compressed, bytes)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(entry.dataset.clone())
+        .bind(entry.url.clone())
+        .bind(format_timestamp(entry.fetched_at))
+        .bind(entry.sha256.clone())
+        .bind(entry.path.clone())
+        .bind(entry.compressed)
+        .bind(entry.bytes)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_observed_schema(&self, schema: &ObservedSchema) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+                INSERT INTO observed_schemas (run_id, dataset, observed_at, fields, fingerprint, drift)
+                VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(schema.run_id.clone())
+        .bind(schema.dataset.clone())
+        .bind(format_timestamp(schema.observed_at))
+        .bind(schema.fields.clone())
+        .bind(schema.fingerprint.clone())
+        .bind(schema.drift.clone())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn revisions(
+        &self,
+        date: Option<&str>,
+        province: Option<&str>,
+    ) -> Result<Vec<Revision>, Error> {
+        // LIKE is case insensitive in sqlite
+        let rows = sqlx::query(
+            r#"
+                SELECT table_name, changed_at, run_id, old_values, new_values
+                FROM revision_history
+                WHERE (?1 IS NULL OR COALESCE(json_extract(new_values, '$.date'), json_extract(new_values, '$.at_date')) LIKE ?1 || '%')
+                AND (?2 IS NULL OR json_extract(new_values, '$.provinsi') LIKE ?2
+                    OR CAST(json_extract(new_values, '$.province_id') AS TEXT) = ?2)
+                ORDER BY changed_at, id
+            "#,
+        )
+        .bind(date.map(|d| d.to_string()))
+        .bind(province.map(|p| p.to_string()))
+        .fetch_all(&self.pool)
+        .await?;
+        let mut revisions = vec![];
+        for row in rows.iter() {
+            let changed_at: String = row.get("changed_at");
+            let old_values: String = row.get("old_values");
+            let new_values: String = row.get("new_values");
+            revisions.push(Revision::new(
+                row.get("table_name"),
+                DateTime::parse_from_rfc3339(changed_at.as_str())?.with_timezone(&Utc),
+                row.get("run_id"),
+                serde_json::from_str(old_values.as_str())?,
+                serde_json::from_str(new_values.as_str())?,
+            ));
+        }
+        Ok(revisions)
+    }
+
+    async fn table_rows(
+        &self,
+        table: &str,
+        columns: &[&str],
+        order_by: &str,
+    ) -> Result<Vec<Value>, Error> {
+        let select = format!(
+            "SELECT json_object({}) AS exported FROM {} ORDER BY {}",
+            columns
+                .iter()
+                .map(|c| format!("'{0}', {0}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table,
+            order_by
+        );
+        let rows = sqlx::query(select.as_str()).fetch_all(&self.pool).await?;
+        let mut values = vec![];
+        for row in rows.iter() {
+            let exported: String = row.get("exported");
+            values.push(serde_json::from_str(exported.as_str())?);
+        }
+        Ok(values)
+    }
+}
+
+pub struct SqliteStoreTx {
+    tx: SqliteTx,
+    run_id: String,
+}
+
+impl SqliteStoreTx {
+    // the row as a json object without its bookkeeping columns, like the
+    // record_revision() trigger stores it on Postgres
+    async fn row_values(&mut self, table: &str, id: i64) -> Result<String, Error> {
+        let columns: Vec<String> = sqlx::query("SELECT name FROM pragma_table_info(?)")
+            .bind(table)
+            .fetch_all(&mut self.tx)
+            .await?
+            .iter()
+            .map(|row| row.get("name"))
+            .filter(|c: &String| {
+                !["id", "existed", "created_at", "updated_at"].contains(&c.as_str())
+            })
+            .collect();
+        let select = format!(
+            "SELECT json_object({}) AS row_values FROM {} WHERE id = ?",
+            columns
+                .iter()
+                .map(|c| format!("'{0}', {0}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table
+        );
+        Ok(sqlx::query(select.as_str())
+            .bind(id)
+            .fetch_one(&mut self.tx)
+            .await?
+            .get("row_values"))
+    }
+
+    // Mirrors the Postgres upserts: the row matching `key` is inserted, or
+    // updated when any of `values` differ, in which case the revision is
+    // recorded. `extra` columns are written but not compared.
+    async fn upsert(
+        &mut self,
+        table: &str,
+        key: &[(&str, Value)],
+        values: &[(&str, Value)],
+        extra: &[(&str, Value)],
+    ) -> Result<Option<bool>, Error> {
+        let compared = key.iter().chain(values.iter()).collect::<Vec<_>>();
+        let select = format!(
+            "SELECT id, json_object({}) AS old_values FROM {} WHERE {}",
+            compared
+                .iter()
+                .map(|(c, _)| format!("'{0}', {0}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table,
+            key.iter()
+                .map(|(c, _)| format!("{} = ?", c))
+                .collect::<Vec<String>>()
+                .join(" AND ")
+        );
+        let old: Option<(i64, String)> =
+            bind_values!(sqlx::query(select.as_str()), key.iter().map(|(_, v)| v))
+                .fetch_optional(&mut self.tx)
+                .await?
+                .map(|row| (row.get("id"), row.get("old_values")));
+        let (id, old_values) = match old {
+            Some(old) => old,
+            None => {
+                let columns = compared
+                    .iter()
+                    .cloned()
+                    .chain(extra.iter())
+                    .collect::<Vec<&(&str, Value)>>();
+                let names = columns.iter().map(|(c, _)| *c).collect::<Vec<&str>>();
+                let insert = format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    table,
+                    names.join(", "),
+                    vec!["?"; names.len()].join(", ")
+                );
+                bind_values!(sqlx::query(insert.as_str()), columns.iter().map(|(_, v)| v))
+                    .execute(&mut self.tx)
+                    .await?;
+                return Ok(Some(true));
+            }
+        };
+        let old_values: Value = serde_json::from_str(old_values.as_str())?;
+        let unchanged = compared
+            .iter()
+            .all(|(c, v)| same_value(v, old_values.get(*c).unwrap_or(&Value::Null)));
+        if unchanged {
+            return Ok(None);
+        }
+        let old_row = self.row_values(table, id).await?;
+        let updated = values.iter().chain(extra.iter()).collect::<Vec<_>>();
+        let update = format!(
+            "UPDATE {} SET {}, existed = 1, updated_at = ? WHERE id = ?",
+            table,
+            updated
+                .iter()
+                .map(|(c, _)| format!("{} = ?", c))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        bind_values!(sqlx::query(update.as_str()), updated.iter().map(|(_, v)| v))
+            .bind(now())
+            .bind(id)
+            .execute(&mut self.tx)
+            .await?;
+        let new_row = self.row_values(table, id).await?;
+        sqlx::query(
+            r#"
+                INSERT INTO revision_history (table_name, row_id, old_values, new_values, run_id, changed_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(table.to_string())
+        .bind(id)
+        .bind(old_row)
+        .bind(new_row)
+        .bind(self.run_id.clone())
+        .bind(now())
+        .execute(&mut self.tx)
+        .await?;
+        Ok(Some(false))
+    }
+}
+
+#[async_trait]
+impl StoreTx for SqliteStoreTx {
+    async fn provinces(&mut self) -> Result<Vec<(i64, String, String)>, Error> {
+        let rows = sqlx::query("SELECT code, name_id, name_en FROM province")
+            .fetch_all(&mut self.tx)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("code"), row.get("name_id"), row.get("name_en")))
+            .collect())
+    }
+
+    async fn upsert_daily(&mut self, prov: &IndoCovidStats) -> Result<Option<bool>, Error> {
+        self.upsert(
+            "covid_daily",
+            &[("date", format_date(prov.date))],
+            &[
+                ("day", json!(prov.day)),
+                ("new_cases_per_day", json!(prov.new_cases_per_day)),
+                ("cumulative_cases", json!(prov.cumulative_cases)),
+                ("under_treatment", json!(prov.under_treatment)),
+                (
+                    "under_treatment_per_day",
+                    json!(prov.under_treatment_per_day),
+                ),
+                (
+                    "under_treatment_percentage",
+                    json!(prov.under_treatment_percentage),
+                ),
+                ("recovered", json!(prov.recovered)),
+                ("recovered_per_day", json!(prov.recovered_per_day)),
+                ("recovered_percentage", json!(prov.recovered_percentage)),
+                ("deaths", json!(prov.deaths)),
+                ("deaths_per_day", json!(prov.deaths_per_day)),
+                ("deaths_percentage", json!(prov.deaths_percentage)),
+            ],
+            &[(
+                "latest_update",
+                json!(prov.latest_update.map(format_timestamp)),
+            )],
+        )
+        .await
+    }
+
+    async fn upsert_province(
+        &mut self,
+        prov: &IndoCovidStats,
+        province_id: i64,
+        unknown: bool,
+    ) -> Result<Option<bool>, Error> {
+        self.upsert(
+            "covid_province",
+            &[
+                ("province_id", json!(province_id)),
+                ("date", format_date(prov.date)),
+            ],
+            &[
+                ("provinsi", json!(prov.provinsi)),
+                ("positif", json!(prov.positif)),
+                ("sembuh", json!(prov.sembuh)),
+                ("meninggal", json!(prov.meninggal)),
+                ("unknown_province", json!(unknown as i64)),
+            ],
+            &[],
+        )
+        .await
+    }
+
+    async fn upsert_stats(&mut self, stats: &CovidStatistics) -> Result<Option<bool>, Error> {
+        self.upsert(
+            "covid_stats",
+            &[("at_date", format_date(stats.created))],
+            &[
+                ("deaths", json!(stats.deaths)),
+                ("total_cases", json!(stats.total_cases)),
+                ("recovered", json!(stats.recovered)),
+                ("pdp", json!(stats.pdp)),
+            ],
+            &[],
+        )
+        .await
+    }
+
+    async fn stored_values(
+        &mut self,
+        table: &str,
+        key: &[(&str, Value)],
+        columns: &[&str],
+    ) -> Result<Option<Value>, Error> {
+        let select = format!(
+            "SELECT json_object({}) AS stored FROM {} WHERE {}",
+            columns
+                .iter()
+                .map(|c| format!("'{0}', {0}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            table,
+            key.iter()
+                .map(|(c, _)| format!("{} = ?", c))
+                .collect::<Vec<String>>()
+                .join(" AND ")
+        );
+        let stored: Option<String> =
+            bind_values!(sqlx::query(select.as_str()), key.iter().map(|(_, v)| v))
+                .fetch_optional(&mut self.tx)
+                .await?
+                .map(|row| row.get("stored"));
+        match stored {
+            Some(s) => Ok(Some(serde_json::from_str(s.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.tx.commit().await?;
+        Ok(())
+    }
+}
+
+pub struct SqliteRunLock {
+    pool: SqlitePool,
+    run_id: String,
+}
+
+#[async_trait]
+impl RunLock for SqliteRunLock {
+    async fn release(self: Box<Self>) -> Result<(), Error> {
+        sqlx::query("DELETE FROM run_lock WHERE run_id = ?")
+            .bind(self.run_id.clone())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::remove_file;
+
+    // a fresh database file per test, the connections of a pool don't share `:memory:`
+    async fn test_store(name: &str) -> Result<(SqliteStore, String), Error> {
+        let path = env::temp_dir()
+            .join(format!("inacovid-{}-{}.db", name, std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = remove_file(path.as_str());
+        let store = SqliteStore::open(format!("sqlite://{}", path).as_str()).await?;
+        store.migrate().await?;
+        Ok((store, path))
+    }
+
+    fn province_row(date: &str) -> IndoCovidStats {
+        serde_json::from_value(json!({"Date": date, "Provinsi": "DKI Jakarta", "Positif": 100}))
+            .expect("Valid province row")
+    }
+
+    async fn insert_lock(store: &SqliteStore, pid: i64, started: Option<i64>) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO run_lock (id, run_id, pid, pid_start, acquired_at) VALUES (1, 'other', ?, ?, ?)",
+        )
+        .bind(pid)
+        .bind(started)
+        .bind(now())
+        .execute(&store.pool)
+        .await?;
+        Ok(())
+    }
+
+    // a gone pid (pid_max is at most 2^22 on linux), a pid reused by another
+    // process since, and our own pid left behind by an earlier process
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn run_lock_of_a_gone_process_is_taken_over() -> Result<(), Error> {
+        let (store, path) = test_store("run-lock").await?;
+        let mut taken = vec![];
+        for (pid, started) in vec![
+            (999999999, None),
+            (1, process_start(1).map(|s| s + 1)),
+            (std::process::id() as i64, None),
+        ] {
+            insert_lock(&store, pid, started).await?;
+            let lock = store.try_lock_run("test").await?;
+            taken.push(lock.is_some());
+            if let Some(lock) = lock {
+                lock.release().await?;
+            }
+        }
+        remove_file(path)?;
+        assert_eq!(taken, vec![true, true, true]);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn run_lock_of_a_live_process_is_kept() -> Result<(), Error> {
+        let (store, path) = test_store("run-lock-live").await?;
+        insert_lock(&store, 1, process_start(1)).await?;
+        let lock = store.try_lock_run("test").await?;
+        let holder = store.run_lock_holder().await?;
+        remove_file(path)?;
+        assert!(lock.is_none());
+        assert!(holder.contains("pid 1 (inacovid other)"), "{}", holder);
+        Ok(())
+    }
+
+    // same shape as the record_revision() trigger stores on Postgres
+    #[tokio::test]
+    async fn revisions_hold_the_whole_row() -> Result<(), Error> {
+        let (store, path) = test_store("revisions").await?;
+        let mut tx = store.begin_run("test").await?;
+        tx.upsert_province(&province_row("2020-05-09"), 31, false)
+            .await?;
+        let mut revised = province_row("2020-05-09");
+        revised.positif = Some(120);
+        assert_eq!(tx.upsert_province(&revised, 31, false).await?, Some(false));
+        tx.commit().await?;
+        let row = sqlx::query("SELECT old_values, new_values FROM revision_history")
+            .fetch_one(&store.pool)
+            .await?;
+        let old_values: String = row.get("old_values");
+        let new_values: String = row.get("new_values");
+        remove_file(path)?;
+        let row = |positif| {
+            json!({
+                "province_id": 31,
+                "date": "2020-05-09",
+                "provinsi": "DKI Jakarta",
+                "positif": positif,
+                "sembuh": null,
+                "meninggal": null,
+                "unknown_province": 0,
+            })
+        };
+        assert_eq!(
+            serde_json::from_str::<Value>(old_values.as_str())?,
+            row(100)
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(new_values.as_str())?,
+            row(120)
+        );
+        Ok(())
+    }
+
+    // the former prov_and_date key collapsed up to ten days of a province into one row
+    #[tokio::test]
+    async fn province_rows_are_kept_per_day_across_month_boundaries() -> Result<(), Error> {
+        let (store, path) = test_store("province-key").await?;
+        let dates = [
+            "2020-05-09",
+            "2020-05-10",
+            "2020-05-19",
+            "2020-05-20",
+            "2020-05-31",
+            "2020-06-01",
+        ];
+        let mut tx = store.begin_run("test").await?;
+        for date in dates.iter() {
+            assert_eq!(
+                tx.upsert_province(&province_row(date), 31, false).await?,
+                Some(true)
+            );
+        }
+        // storing them again leaves every row as it is
+        for date in dates.iter() {
+            assert_eq!(
+                tx.upsert_province(&province_row(date), 31, false).await?,
+                None
+            );
+        }
+        tx.commit().await?;
+        let rows = store
+            .table_rows("covid_province", &["province_id", "date"], "date")
+            .await?;
+        remove_file(path)?;
+        assert_eq!(
+            rows,
+            dates
+                .iter()
+                .map(|d| json!({"province_id": 31, "date": d}))
+                .collect::<Vec<Value>>()
+        );
+        Ok(())
+    }
+}
@@ -1,16 +1,187 @@
-use sqlx::{pool::PoolConnection, postgres::PgPool, Error as SqlxError, PgConnection, Transaction};
+use crate::archive::ArchiveEntry;
+use crate::drift::ObservedSchema;
+use crate::history::Revision;
+use crate::migrations::{latest_version, Migration};
+use crate::models::{CovidStatistics, IndoCovidStats, UpsertCounts};
+use crate::pgstore::PgStore;
+use crate::province::Province;
+use crate::run::LockPolicy;
+use crate::sqlite::SqliteStore;
+use async_trait::async_trait;
+use failure::Error;
+use serde_json::Value;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::delay_for;
 
-pub struct PgStore {
-    pool: Arc<PgPool>,
+const RUN_LOCK_POLL: Duration = Duration::from_secs(5);
+
+// ------ Storage backends -------- //
+// Everything the ingestion persists goes through these traits, implemented by
+// `PgStore` and by `SqliteStore` for self-contained runs.
+#[async_trait]
+pub trait Store: Send + Sync {
+    fn migrations(&self) -> &'static [Migration];
+    // highest applied migration, 0 when migrations were never run
+    async fn schema_version(&self) -> Result<i64, Error>;
+    // applies pending migrations, each in its own transaction, returns the applied versions
+    async fn migrate(&self) -> Result<Vec<i64>, Error>;
+    async fn seed_provinces(&self, provinces: &[Province]) -> Result<(), Error>;
+
+    // transaction tagged with the ingestion run, so revisions can be traced back to it
+    async fn begin_run(&self, run_id: &str) -> Result<Box<dyn StoreTx>, Error>;
+    // `None` when another run holds the lock
+    async fn try_lock_run(&self, run_id: &str) -> Result<Option<Box<dyn RunLock>>, Error>;
+    async fn run_lock_holder(&self) -> Result<String, Error>;
+    async fn start_run(&self, run_id: &str, atomic: bool) -> Result<(), Error>;
+    async fn finish_run(
+        &self,
+        run_id: &str,
+        status: &str,
+        counts: &str,
+        error: Option<String>,
+    ) -> Result<(), Error>;
+
+    async fn insert_archive(&self, entry: &ArchiveEntry) -> Result<(), Error>;
+    async fn insert_observed_schema(&self, schema: &ObservedSchema) -> Result<(), Error>;
+    // revisions of a reporting date (`YYYY-MM-DD`) and/or a province (name or BPS code), oldest first
+    async fn revisions(
+        &self,
+        date: Option<&str>,
+        province: Option<&str>,
+    ) -> Result<Vec<Revision>, Error>;
+    // every row of `table` as a json object of `columns`, for exports
+    async fn table_rows(
+        &self,
+        table: &str,
+        columns: &[&str],
+        order_by: &str,
+    ) -> Result<Vec<Value>, Error>;
+
+    // refuses to ingest into a database this binary was not built for
+    async fn verify_schema(&self) -> Result<(), Error> {
+        let current = self.schema_version().await?;
+        let latest = latest_version(self.migrations());
+        if current < latest {
+            return Err(format_err!(
+                "Database schema is at version {}, expected {}: run `inacovid migrate` or pass --auto-migrate",
+                current,
+                latest
+            ));
+        }
+        if current > latest {
+            return Err(format_err!(
+                "Database schema is at version {}, newer than this binary supports ({})",
+                current,
+                latest
+            ));
+        }
+        Ok(())
+    }
+
+    // takes the ingestion lock, `None` means another run holds it and the policy is to skip
+    async fn lock_run(
+        &self,
+        run_id: &str,
+        policy: LockPolicy,
+    ) -> Result<Option<Box<dyn RunLock>>, Error> {
+        loop {
+            if let Some(lock) = self.try_lock_run(run_id).await? {
+                return Ok(Some(lock));
+            }
+            let holder = self.run_lock_holder().await?;
+            match policy {
+                LockPolicy::Fail => {
+                    return Err(format_err!(
+                        "Another ingestion run holds the lock: {}",
+                        holder
+                    ))
+                }
+                LockPolicy::Skip => {
+                    eprintln!("Another ingestion run holds the lock, skipping: {}", holder);
+                    return Ok(None);
+                }
+                LockPolicy::Wait => {
+                    eprintln!(
+                        "Another ingestion run holds the lock, waiting {}s: {}",
+                        RUN_LOCK_POLL.as_secs(),
+                        holder
+                    );
+                    delay_for(RUN_LOCK_POLL).await;
+                }
+            }
+        }
+    }
+}
+
+// upserts return `Some(true)` when inserted, `Some(false)` when revised and
+// `None` when the stored figures were already the same
+#[async_trait]
+pub trait StoreTx: Send {
+    // (code, name_id, name_en) of the province reference table
+    async fn provinces(&mut self) -> Result<Vec<(i64, String, String)>, Error>;
+    async fn upsert_daily(&mut self, row: &IndoCovidStats) -> Result<Option<bool>, Error>;
+    async fn upsert_province(
+        &mut self,
+        row: &IndoCovidStats,
+        province_id: i64,
+        unknown: bool,
+    ) -> Result<Option<bool>, Error>;
+    async fn upsert_stats(&mut self, stats: &CovidStatistics) -> Result<Option<bool>, Error>;
+
+    // bulk variants for large batches, backends without a faster path upsert row by row
+    async fn upsert_daily_bulk(&mut self, rows: &[&IndoCovidStats]) -> Result<UpsertCounts, Error> {
+        let mut counts = UpsertCounts::default();
+        for row in rows {
+            counts.record(self.upsert_daily(row).await?);
+        }
+        Ok(counts)
+    }
+    async fn upsert_province_bulk(
+        &mut self,
+        rows: &[(&IndoCovidStats, i64, bool)],
+    ) -> Result<UpsertCounts, Error> {
+        let mut counts = UpsertCounts::default();
+        for (row, province_id, unknown) in rows {
+            counts.record(self.upsert_province(row, *province_id, *unknown).await?);
+        }
+        Ok(counts)
+    }
+
+    // current values of `columns` in the row of `table` matching `key`, as a json
+    // object, `None` when there is no such row
+    async fn stored_values(
+        &mut self,
+        table: &str,
+        key: &[(&str, Value)],
+        columns: &[&str],
+    ) -> Result<Option<Value>, Error>;
+    async fn commit(self: Box<Self>) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait RunLock: Send {
+    async fn release(self: Box<Self>) -> Result<(), Error>;
 }
 
-impl PgStore {
-    pub fn new(pool: Arc<sqlx::PgPool>) -> Self {
-        PgStore { pool: pool }
+// compares stored and fetched column values, floats may come back from the
+// database's json functions with fewer digits so they only have to be close
+pub fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => match (x.as_f64(), y.as_f64()) {
+            (Some(x), Some(y)) => (x - y).abs() <= 1e-9 * x.abs().max(y.abs()).max(1.0),
+            _ => x == y,
+        },
+        _ => a == b,
     }
+}
 
-    pub async fn get_tx(&self) -> Result<Transaction<PoolConnection<PgConnection>>, SqlxError> {
-        self.pool.begin().await
+// `sqlite://path/to/inacovid.db` opens an embedded database, anything else is
+// taken as a postgres dsn
+pub async fn open(uri: &str) -> Result<Box<dyn Store>, Error> {
+    if uri.starts_with("sqlite:") {
+        return Ok(Box::new(SqliteStore::open(uri).await?));
     }
+    let pool = sqlx::PgPool::new(uri).await?;
+    Ok(Box::new(PgStore::new(uri, Arc::new(pool))))
 }